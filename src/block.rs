@@ -1,66 +1,117 @@
-use super::{Hashable, Transaction};
+use super::transaction::transactions_root;
+use super::{
+    BlockHash, Encode, Hashable, PowHasher, Sha256, Transaction, ValidationError, ENCODING_VERSION,
+    UTXO_ROOT_ENCODING_VERSION,
+};
 use serde::{Deserialize, Serialize};
 
 /// A block contains the predecessor block hash, a list of transactions, the target
 /// difficulty, the nonce, and the block’s hash.
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Block {
     pub difficulty: u32,
-    pub hash: String,
+    pub hash: BlockHash,
     pub nonce: u64,
-    pub predecessor: String,
+    /// The hash of the predecessor block, [zero](struct.BlockHash.html#associatedconstant.ZERO)
+    /// for a genesis block.
+    pub predecessor: BlockHash,
     pub transactions: Vec<Transaction>,
+    /// The [root](struct.UtxoSet.html#method.root) of the unspent outputs after the block, on
+    /// chains [committing](struct.ChainParams.html#structfield.utxo_commitment) to them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub utxo_root: Option<BlockHash>,
 }
 
-impl Hashable for Block {
-    /// Returns an encoded version of a block, which would later be hashed.
-    fn bytes(&self) -> Vec<u8> {
-        let mut ms = String::from("[\"");
+/// A block header contains everything in a [block](struct.Block.html) except its transactions,
+/// which are committed to by the transactions root.
+///
+/// Since the block hash is computed from the header alone, the proof of work of a chain can be
+/// verified from its headers before downloading any transactions.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlockHeader {
+    pub difficulty: u32,
+    pub hash: BlockHash,
+    pub nonce: u64,
+    pub predecessor: BlockHash,
+    pub transactions_root: BlockHash,
+    /// See [Block::utxo_root](struct.Block.html#structfield.utxo_root).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub utxo_root: Option<BlockHash>,
+}
 
-        ms.push_str(&self.predecessor);
-        ms.push_str("\",[");
+impl Hashable for BlockHeader {
+    /// Returns the [canonical encoding](trait.Encode.html) of the block header without its
+    /// hash, which would later be hashed.
+    fn bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.encoding_version().encode_to(&mut bytes);
+        self.difficulty.encode_to(&mut bytes);
+        self.nonce.encode_to(&mut bytes);
+        self.predecessor.encode_to(&mut bytes);
+        self.transactions_root.encode_to(&mut bytes);
+        if let Some(utxo_root) = &self.utxo_root {
+            utxo_root.encode_to(&mut bytes);
+        }
+        bytes
+    }
+}
 
-        let txnslen = self.transactions.len();
+impl BlockHeader {
+    /// Returns the version of the [encoding](trait.Encode.html) of the header:
+    /// [UTXO_ROOT_ENCODING_VERSION](constant.UTXO_ROOT_ENCODING_VERSION.html) if it commits to a
+    /// UTXO root, otherwise [ENCODING_VERSION](constant.ENCODING_VERSION.html).
+    pub fn encoding_version(&self) -> u8 {
+        match self.utxo_root {
+            Some(_) => UTXO_ROOT_ENCODING_VERSION,
+            None => ENCODING_VERSION,
+        }
+    }
+    /// Validates if the block header was mined correctly.
+    ///
+    /// `validate` checks whether:
+    /// * the provided hash is same as the computed hash.
+    /// * the provided value of difficulty is within the limits.
+    /// * the number of leading zeroes in block hash matches the difficulty.
+    ///
+    /// Returns the first check that failed, if any.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.validate_with(&Sha256)
+    }
+    /// Validates if the block header was mined correctly with the given
+    /// [proof-of-work algorithm](trait.PowHasher.html).
+    ///
+    /// See [validate](#method.validate).
+    pub fn validate_with(&self, hasher: &dyn PowHasher) -> Result<(), ValidationError> {
+        let computed_hash = self.hash_with(hasher);
+        if self.hash != computed_hash {
+            return Err(ValidationError::InvalidHash);
+        }
 
-        for (i, txn) in self.transactions.iter().enumerate() {
-            ms.push_str("{\"inputs\":[");
-            let iplen = txn.inputs.len();
-            for (ip_i, ip) in txn.inputs.iter().enumerate() {
-                ms.push_str(&format!("{{\"amount\":{},\"id\":{}}}", ip.amount, ip.id));
-                if ip_i < iplen - 1 {
-                    ms.push_str(",");
-                }
-            }
-            ms.push_str("],");
-            ms.push_str("\"outputs\":[");
-            let oplen = txn.outputs.len();
-            for (op_i, op) in txn.outputs.iter().enumerate() {
-                ms.push_str(&format!("{{\"amount\":{},\"id\":{}}}", op.amount, op.id));
-                if op_i < oplen - 1 {
-                    ms.push_str(",");
-                }
-            }
-            ms.push_str("]}");
-            if i < txnslen - 1 {
-                ms.push_str(",");
-            }
+        if self.difficulty > 64 {
+            // difficulty can't be greater than 64 since the hash
+            // has 64 hexadecimal digits.
+            return Err(ValidationError::DifficultyTooHigh);
+        }
+        if computed_hash.leading_zero_digits() >= self.difficulty {
+            return Ok(());
         }
-        ms.push_str("],");
-        ms.push_str(&format!("{},{}", &self.difficulty, &self.nonce));
-        ms.push_str("]");
+        Err(ValidationError::LeadingZeroes)
+    }
+}
 
-        let mut bss = vec![];
-        bss.extend(ms.as_bytes());
-        bss
+impl Hashable for Block {
+    /// Returns an encoded version of the block header, which would later be hashed.
+    fn bytes(&self) -> Vec<u8> {
+        self.header().bytes()
     }
 }
 
 impl Block {
     pub fn new(
         difficulty: u32,
-        hash: String,
+        hash: BlockHash,
         nonce: u64,
-        predecessor: String,
+        predecessor: BlockHash,
         transactions: Vec<Transaction>,
     ) -> Self {
         Block {
@@ -69,34 +120,83 @@ impl Block {
             nonce,
             predecessor,
             transactions,
+            utxo_root: None,
+        }
+    }
+    /// Returns the [canonical encoding](trait.Encode.html) of the list of transactions of the
+    /// block.
+    pub fn transactions_bytes(&self) -> Vec<u8> {
+        self.transactions.encode()
+    }
+    /// Returns the root of a Merkle tree over the transactions of the block, in block order.
+    ///
+    /// Each leaf is the SHA256 hash of a `0` byte followed by the
+    /// [encoded transaction](trait.Encode.html), each inner node the hash of a `1` byte followed
+    /// by its two children, and a node without a sibling moves up a level unchanged. The root is
+    /// the hash of the number of transactions as a `u32` followed by the top of the tree, if
+    /// any, so that the root of a block without transactions is the hash of an encoded empty
+    /// list. A [TransactionProof](struct.TransactionProof.html) proves a transaction is in the
+    /// block with a branch of the tree.
+    pub fn transactions_root(&self) -> BlockHash {
+        transactions_root(&self.transactions)
+    }
+    /// Returns the version of the [encoding](trait.Encode.html) of the block, see
+    /// [BlockHeader::encoding_version](struct.BlockHeader.html#method.encoding_version).
+    pub fn encoding_version(&self) -> u8 {
+        match self.utxo_root {
+            Some(_) => UTXO_ROOT_ENCODING_VERSION,
+            None => ENCODING_VERSION,
+        }
+    }
+    /// Returns the header of the block.
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            difficulty: self.difficulty,
+            hash: self.hash,
+            nonce: self.nonce,
+            predecessor: self.predecessor,
+            transactions_root: self.transactions_root(),
+            utxo_root: self.utxo_root,
         }
     }
     /// Validates if the submitted block was mined correctly.
     ///
-    /// `validate` checks whether:
-    /// * the provided hash is same as the computed hash.
-    /// * the provided value of difficulty is within the limits.
-    /// * the number of leading zeroes in block hash matches the difficulty.
+    /// See [BlockHeader::validate](struct.BlockHeader.html#method.validate).
+    pub fn validate(&mut self) -> Result<(), ValidationError> {
+        self.header().validate()
+    }
+    /// Mines the block: searches for a nonce, starting from the current one, giving the block a
+    /// valid hash under the given [proof-of-work algorithm](trait.PowHasher.html), and sets the hash.
     ///
-    /// Returns `true` if valid, otherwise returns false.
-    pub fn validate(&mut self) -> bool {
-        let computed_hash = self.hash();
-        if self.hash != computed_hash {
-            println!("{{\"error\":\"invalid hash\"}}");
-            return false;
-        }
-
+    /// Returns an error if the difficulty is too high to ever be met.
+    pub fn mine(&mut self, hasher: &dyn PowHasher) -> Result<(), ValidationError> {
         if self.difficulty > 64 {
-            // difficulty can't be greater than 64 since the hash
-            // has 64 characters (excluding the prefix "0x").
-            println!("{{\"error\":\"maximum value of difficulty is 64\"}}");
-            return false;
+            return Err(ValidationError::DifficultyTooHigh);
         }
-        let (hash_start, _) = computed_hash.split_at((2 + self.difficulty) as usize);
-        if hash_start == format!("0x{}", "0".repeat(self.difficulty as usize)) {
-            return true;
+        loop {
+            self.hash = self.hash_with(hasher);
+            if self.validate_with(hasher).is_ok() {
+                return Ok(());
+            }
+            self.nonce = self.nonce.wrapping_add(1);
+        }
+    }
+    /// Checks the rules specific to a genesis block: its predecessor is zero and its
+    /// transactions have no inputs.
+    pub fn check_genesis(&self) -> Result<(), ValidationError> {
+        if self.predecessor != BlockHash::ZERO {
+            return Err(ValidationError::GenesisPredecessor);
         }
-        println!("{{\"error\":\"leading zeroes in block hash did not match difficulty\"}}");
-        return false;
+        if self.transactions.iter().any(|t| !t.inputs.is_empty()) {
+            return Err(ValidationError::GenesisInputs);
+        }
+        Ok(())
+    }
+    /// Validates if the submitted block was mined correctly with the given
+    /// [proof-of-work algorithm](trait.PowHasher.html).
+    ///
+    /// See [BlockHeader::validate](struct.BlockHeader.html#method.validate).
+    pub fn validate_with(&self, hasher: &dyn PowHasher) -> Result<(), ValidationError> {
+        self.header().validate_with(hasher)
     }
 }
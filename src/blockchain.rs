@@ -1,6 +1,9 @@
-use super::{Block, Output};
+use super::{
+    Block, BlockHash, ChainParams, Output, UtxoFilter, UtxoSet, UtxoUndo, ValidationError,
+};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::num::NonZeroUsize;
 
 /// A helper struct used to parse json inputs to submit a new block to the chain.
 #[derive(Serialize, Deserialize)]
@@ -14,20 +17,99 @@ pub struct InitGenesis {
     pub init: Block,
 }
 
-/// A struct that keeps track of a single chain in the [network](struct.Network.html).
+/// A helper struct used to parse json inputs to mine blocks on demand, see
+/// [Network::generate](struct.Network.html#method.generate).
+#[derive(Serialize, Deserialize)]
+pub struct GenerateBlocks {
+    /// The number of blocks to mine.
+    pub generate: u64,
+    /// The id of the coinbase output of the first block.
+    pub to: u64,
+    /// The hash of the block to mine on, the main chain head if missing.
+    pub on: Option<BlockHash>,
+}
+
+/// A helper struct used to parse json queries about the blocks of the
+/// [network](struct.Network.html#method.query_json), tagged by their `query` field.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "query", rename_all = "snake_case")]
+pub enum ChainQuery {
+    /// A block given by its hash, or by its height in the main chain.
+    Block {
+        hash: Option<BlockHash>,
+        height: Option<u64>,
+    },
+    /// Up to `count` ancestors of a block, all of them if missing.
+    Ancestors {
+        hash: BlockHash,
+        count: Option<usize>,
+    },
+    /// The latest block that two blocks, e.g. two heads, have in common.
+    CommonAncestor { hashes: [BlockHash; 2] },
+    /// The blocks from `from` to `to`, which must be its descendant.
+    Range { from: BlockHash, to: BlockHash },
+    /// A page of the unspent outputs of the given head, the main chain head if missing,
+    /// selected by the filter. See [UtxoSet::page](struct.UtxoSet.html#method.page). A `limit`
+    /// of `0` is rejected, since the page would end the paging with no cursor.
+    Utxos {
+        head: Option<BlockHash>,
+        #[serde(flatten)]
+        filter: UtxoFilter,
+        after: Option<Output>,
+        limit: Option<NonZeroUsize>,
+    },
+    /// The state as of a block given by its hash, or by its height in the main chain: its
+    /// height, totalWork and unspent outputs.
+    StateAt {
+        hash: Option<BlockHash>,
+        height: Option<u64>,
+    },
+    /// The outputs created and spent from the block `from` to the block `to`, on any branches.
+    Diff { from: BlockHash, to: BlockHash },
+    /// The differences between the chains of two heads since their common ancestor, including
+    /// the outputs spent differently in each chain.
+    CompareHeads { heads: [BlockHash; 2] },
+    /// A proof that the output is, or is not, unspent as of the given block, the main chain
+    /// head if missing.
+    Prove {
+        hash: Option<BlockHash>,
+        output: Output,
+    },
+    /// A proof that the transaction creating the output is in the given block.
+    ProveTransaction { hash: BlockHash, output: Output },
+    /// The tree of all the known blocks, as JSON if the format is missing.
+    Tree { format: Option<TreeFormat> },
+}
+
+/// The formats the block tree of the [network](struct.Network.html#method.tree_json) can be
+/// exported to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TreeFormat {
+    /// Graphviz DOT, see [Network::tree_dot](struct.Network.html#method.tree_dot).
+    Dot,
+    /// Nested JSON, see [Network::tree_json](struct.Network.html#method.tree_json).
+    Json,
+}
+
+/// A struct that keeps track of the state of a single chain in the [network](struct.Network.html).
 ///
-/// The `Blockchain` is analogous to a single chain (or fork) in a network.
+/// The `Blockchain` is analogous to a single chain (or fork) in a network, whose blocks are
+/// kept in the [block index](struct.Network.html#structfield.block_index) of the network.
 /// In this client, the methods of `Blockchain` are invoked from the methods in [Network](struct.Network.html#impl).
 #[derive(Clone)]
 pub struct Blockchain {
-    /// A list of all the blocks and their creation timestamps in the chain.
-    pub blocks: Vec<(Block, u128)>,
-    /// It stores the block hashes of all the blocks in the chain.
-    pub blocks_set: HashSet<String>,
-    /// A list of the unspent transaction outputs in the chain.
-    pub outputs: Vec<Output>,
-    /// It stores the unspent transaction outputs in the chain.
-    pub outputs_set: HashSet<Output>,
+    /// The unspent transaction outputs in the chain.
+    pub utxos: UtxoSet,
+    /// The height of the last block of the chain, the genesis block being at height 1, or `0`
+    /// if the chain is empty.
+    pub height: u64,
+}
+
+impl Default for Blockchain {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Blockchain {
@@ -38,68 +120,302 @@ impl Blockchain {
     /// Basic usage:
     ///
     /// ```
+    /// # use mycoinlib::Blockchain;
     /// let blockchain = Blockchain::new();
     /// ```
     pub fn new() -> Self {
         Blockchain {
-            blocks: vec![],
-            blocks_set: HashSet::new(),
-            outputs: vec![],
-            outputs_set: HashSet::new(),
+            utxos: UtxoSet::new(),
+            height: 0,
         }
     }
     /// Initializes the chain with the genesis block.
     ///
     /// Returns `true` if the chain was initialized successful, otherwise returns `false`.
-    pub fn init(&mut self, block: Block, timestamp: u128) -> bool {
+    pub fn init(&mut self, block: &Block) -> bool {
         // init genesis block and return true/false
 
-        let mut blocks_spent: HashSet<Output> = HashSet::new();
-        let mut blocks_created: HashSet<Output> = HashSet::new();
-        for transaction in &block.transactions {
-            blocks_spent.extend(transaction.inputs());
-            blocks_created.extend(transaction.outputs());
-        }
-
-        self.outputs_set
-            .retain(|output| !blocks_spent.contains(output));
-        self.outputs_set.extend(blocks_created);
-        self.outputs = self.outputs_set.clone().into_iter().collect();
-
-        self.blocks.push((block.clone(), timestamp));
-        self.blocks_set.insert(block.hash.to_owned());
+        self.utxos.apply(block, 1);
+        self.height = 1;
         true
     }
     /// Submits a new block to the chain.
     ///
-    /// Returns `true` if the block was added successful, otherwise returns `false`.
-    pub fn submit(&mut self, block: Block, timestamp: u128) -> bool {
+    /// Returns an error if a transaction of the block is invalid (e.g. it lists an input twice
+    /// or its amounts overflow), if the coinbase transactions create more than the [block subsidy](struct.ChainParams.html#method.block_subsidy) or if
+    /// a coinbase output is spent before
+    /// [maturity](struct.ChainParams.html#structfield.coinbase_maturity), or if the block has
+    /// the wrong [UTXO root](#method.check_utxo_root). Otherwise returns the changes made to the
+    /// [UTXO set](#structfield.utxos).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{
+    /// #     Block, BlockHash, Blockchain, ChainParams, Output, Transaction, ValidationError,
+    /// # };
+    /// let params = ChainParams {
+    ///     coinbase_maturity: 2,
+    ///     ..ChainParams::regtest()
+    /// };
+    /// let coin = Output { id: 1, amount: 50 };
+    /// let coinbase = |amount| Transaction {
+    ///     inputs: vec![],
+    ///     outputs: vec![Output { id: 1, amount }],
+    /// };
+    /// let spend = Transaction {
+    ///     inputs: vec![coin],
+    ///     outputs: vec![Output { id: 2, amount: 50 }],
+    /// };
+    /// let block = |transactions| Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, transactions);
+    ///
+    /// let mut chain = Blockchain::new();
+    /// chain.init(&block(vec![]));
+    /// let too_much = coinbase(params.subsidy.initial + 1);
+    /// assert_eq!(
+    ///     chain.submit(&block(vec![too_much]), &params),
+    ///     Err(ValidationError::InvalidCoinbase)
+    /// );
+    /// chain.submit(&block(vec![coinbase(50)]), &params).unwrap();
+    /// assert_eq!(
+    ///     chain.submit(&block(vec![spend.clone()]), &params),
+    ///     Err(ValidationError::ImmatureCoinbase)
+    /// );
+    /// chain.submit(&block(vec![]), &params).unwrap();
+    /// chain.submit(&block(vec![spend]), &params).unwrap();
+    /// assert_eq!(chain.height, 4);
+    /// ```
+    pub fn submit(
+        &mut self,
+        block: &Block,
+        params: &ChainParams,
+    ) -> Result<UtxoUndo, ValidationError> {
+        let height = self.height + 1;
+        let mut coinbase_value: u64 = 0;
         let mut blocks_spent: HashSet<Output> = HashSet::new();
-        let mut blocks_created: HashSet<Output> = HashSet::new();
         for transaction in &block.transactions {
+            if transaction.is_coinbase() {
+                coinbase_value = transaction
+                    .checked_output_value()
+                    .and_then(|value| coinbase_value.checked_add(value))
+                    .ok_or(ValidationError::InvalidCoinbase)?;
+                continue;
+            }
             let inputs = transaction.inputs();
-            if !(&inputs - &self.outputs_set).is_empty() || !(&inputs & &blocks_spent).is_empty() {
-                println!("{{\"error\":\"invalid transaction\"}}");
-                return false;
+            if transaction.has_duplicate_inputs()
+                || !inputs.iter().all(|input| self.utxos.contains(input))
+                || !(&inputs & &blocks_spent).is_empty()
+            {
+                return Err(ValidationError::InvalidTransaction);
+            }
+            // The outputs of the genesis block are always mature.
+            if inputs.iter().any(|input| {
+                self.utxos.get(input).is_some_and(|entry| {
+                    entry.coinbase
+                        && entry.height > 1
+                        && height
+                            .checked_sub(entry.height)
+                            .is_none_or(|age| age < params.coinbase_maturity)
+                })
+            }) {
+                return Err(ValidationError::ImmatureCoinbase);
             }
 
-            let input_value = transaction.input_value();
-            let output_value = transaction.output_value();
-            if output_value != input_value {
-                println!("{{\"error\":\"invalid transaction\"}}");
-                return false;
+            let input_value = transaction.checked_input_value();
+            let output_value = transaction.checked_output_value();
+            if input_value.is_none() || output_value != input_value {
+                return Err(ValidationError::InvalidTransaction);
             }
             blocks_spent.extend(inputs);
-            blocks_created.extend(transaction.outputs());
+        }
+        if coinbase_value > params.block_subsidy(height) {
+            return Err(ValidationError::InvalidCoinbase);
         }
 
-        self.outputs_set
-            .retain(|output| !blocks_spent.contains(output));
-        self.outputs_set.extend(blocks_created);
-        self.outputs = self.outputs_set.clone().into_iter().collect();
+        let undo = self.utxos.apply(block, height);
+        if let Err(e) = self.check_utxo_root(block, params) {
+            self.utxos.undo(&undo);
+            return Err(e);
+        }
+        self.height = height;
+        Ok(undo)
+    }
+    /// Checks the [UTXO root](struct.Block.html#structfield.utxo_root) of a block just applied to
+    /// the chain: it must be the [root](struct.UtxoSet.html#method.root) of the unspent outputs
+    /// on chains [committing](struct.ChainParams.html#structfield.utxo_commitment) to them, and
+    /// missing on other chains.
+    pub fn check_utxo_root(
+        &self,
+        block: &Block,
+        params: &ChainParams,
+    ) -> Result<(), ValidationError> {
+        let expected = match params.utxo_commitment {
+            true => Some(self.utxos.root()),
+            false => None,
+        };
+        if block.utxo_root != expected {
+            return Err(ValidationError::InvalidUtxoRoot);
+        }
+        Ok(())
+    }
+}
 
-        self.blocks.push((block.clone(), timestamp));
-        self.blocks_set.insert(block.hash.to_owned());
-        true
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Transaction;
+
+    fn block(transactions: Vec<Transaction>) -> Block {
+        Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, transactions)
+    }
+
+    fn coinbase(amounts: &[u64]) -> Transaction {
+        Transaction {
+            inputs: vec![],
+            outputs: amounts
+                .iter()
+                .enumerate()
+                .map(|(id, &amount)| Output {
+                    id: id as u64,
+                    amount,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn overflowing_coinbase_is_rejected() {
+        let params = ChainParams::regtest();
+        let mut chain = Blockchain::new();
+        chain.init(&block(vec![]));
+        let wrapping = coinbase(&[u64::MAX, 51]);
+        assert_eq!(
+            chain.submit(&block(vec![wrapping]), &params),
+            Err(ValidationError::InvalidCoinbase)
+        );
+        let split = vec![coinbase(&[u64::MAX]), coinbase(&[51])];
+        assert_eq!(
+            chain.submit(&block(split), &params),
+            Err(ValidationError::InvalidCoinbase)
+        );
+        assert_eq!(chain.height, 1);
+    }
+
+    #[test]
+    fn overflowing_transaction_is_rejected() {
+        let params = ChainParams::regtest();
+        let mut chain = Blockchain::new();
+        chain.init(&block(vec![coinbase(&[u64::MAX, 2])]));
+        let spend = Transaction {
+            inputs: vec![
+                Output {
+                    id: 0,
+                    amount: u64::MAX,
+                },
+                Output { id: 1, amount: 2 },
+            ],
+            outputs: vec![Output { id: 7, amount: 1 }],
+        };
+        assert_eq!(
+            chain.submit(&block(vec![spend]), &params),
+            Err(ValidationError::InvalidTransaction)
+        );
+    }
+
+    #[test]
+    fn duplicate_inputs_are_rejected() {
+        let params = ChainParams::regtest();
+        let mut chain = Blockchain::new();
+        chain.init(&block(vec![coinbase(&[50])]));
+        let coin = Output { id: 0, amount: 50 };
+        let doubled = Transaction {
+            inputs: vec![coin, coin],
+            outputs: vec![Output { id: 7, amount: 100 }],
+        };
+        assert_eq!(
+            chain.submit(&block(vec![doubled]), &params),
+            Err(ValidationError::InvalidTransaction)
+        );
+        let single = Transaction {
+            inputs: vec![coin],
+            outputs: vec![Output { id: 7, amount: 50 }],
+        };
+        assert!(chain.submit(&block(vec![single]), &params).is_ok());
+    }
+
+    #[test]
+    fn coinbase_may_create_exactly_the_subsidy() {
+        let params = ChainParams::regtest();
+        let subsidy = params.block_subsidy(2);
+        let mut chain = Blockchain::new();
+        chain.init(&block(vec![]));
+        assert_eq!(
+            chain.submit(&block(vec![coinbase(&[subsidy, 1])]), &params),
+            Err(ValidationError::InvalidCoinbase)
+        );
+        let split = vec![coinbase(&[subsidy - 1]), coinbase(&[1])];
+        assert!(chain.submit(&block(split), &params).is_ok());
+        assert_eq!(chain.height, 2);
+    }
+
+    #[test]
+    fn duplicate_inputs_across_transactions_are_rejected() {
+        let params = ChainParams::regtest();
+        let mut chain = Blockchain::new();
+        chain.init(&block(vec![coinbase(&[50])]));
+        let spend = |id| Transaction {
+            inputs: vec![Output { id: 0, amount: 50 }],
+            outputs: vec![Output { id, amount: 50 }],
+        };
+        assert_eq!(
+            chain.submit(&block(vec![spend(7), spend(8)]), &params),
+            Err(ValidationError::InvalidTransaction)
+        );
+    }
+
+    #[test]
+    fn coinbase_matures_after_exactly_the_maturity() {
+        let params = ChainParams {
+            coinbase_maturity: 3,
+            ..ChainParams::regtest()
+        };
+        let spend = |id| Transaction {
+            inputs: vec![Output { id: 0, amount: 50 }],
+            outputs: vec![Output { id, amount: 50 }],
+        };
+        let mut chain = Blockchain::new();
+        chain.init(&block(vec![]));
+        chain
+            .submit(&block(vec![coinbase(&[50])]), &params)
+            .unwrap();
+        chain.submit(&block(vec![]), &params).unwrap();
+        assert_eq!(
+            chain.submit(&block(vec![spend(7)]), &params),
+            Err(ValidationError::ImmatureCoinbase)
+        );
+        chain.submit(&block(vec![]), &params).unwrap();
+        assert!(chain.submit(&block(vec![spend(7)]), &params).is_ok());
+    }
+
+    #[test]
+    fn genesis_outputs_are_always_mature() {
+        let params = ChainParams {
+            coinbase_maturity: 100,
+            ..ChainParams::regtest()
+        };
+        let mut chain = Blockchain::new();
+        chain.init(&block(vec![coinbase(&[50])]));
+        assert!(
+            chain
+                .utxos
+                .get(&Output { id: 0, amount: 50 })
+                .unwrap()
+                .coinbase
+        );
+        let spend = Transaction {
+            inputs: vec![Output { id: 0, amount: 50 }],
+            outputs: vec![Output { id: 7, amount: 50 }],
+        };
+        assert!(chain.submit(&block(vec![spend]), &params).is_ok());
     }
 }
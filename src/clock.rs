@@ -0,0 +1,63 @@
+use super::now;
+use std::sync::{Arc, Mutex};
+
+/// A source of unix timestamps (in milliseconds) for the [Network](struct.Network.html).
+///
+/// The time at which a block is received decides which of two heads with equal `totalWork` is
+/// the main chain, so passing a [ManualClock](struct.ManualClock.html) makes fork choice
+/// deterministic.
+pub trait Clock: Send {
+    /// Returns the current unix timestamp.
+    fn now(&self) -> u128;
+}
+
+/// A clock that returns the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u128 {
+        now()
+    }
+}
+
+/// A clock that only moves when told to.
+///
+/// Clones share the same time, so a test can keep a clone to drive the clock of a `Network`.
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::{Clock, ManualClock};
+/// let clock = ManualClock::new(1_000);
+/// let handle = clock.clone();
+/// handle.advance(500);
+/// assert_eq!(clock.now(), 1_500);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    time: Arc<Mutex<u128>>,
+}
+
+impl ManualClock {
+    /// Creates a new `ManualClock` instance set to the given timestamp.
+    pub fn new(time: u128) -> Self {
+        ManualClock {
+            time: Arc::new(Mutex::new(time)),
+        }
+    }
+    /// Sets the current timestamp.
+    pub fn set(&self, time: u128) {
+        *self.time.lock().unwrap() = time;
+    }
+    /// Moves the clock forward by the given number of milliseconds.
+    pub fn advance(&self, millis: u128) {
+        *self.time.lock().unwrap() += millis;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> u128 {
+        *self.time.lock().unwrap()
+    }
+}
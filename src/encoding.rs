@@ -0,0 +1,320 @@
+use super::{Block, BlockHash, BlockHeader, DecodeError, Output, Transaction, UtxoEntry};
+
+/// The version of the binary encoding, written at the start of every encoded block and header.
+pub const ENCODING_VERSION: u8 = 4;
+
+/// The version of the binary encoding of the blocks and headers that commit to a
+/// [UTXO root](struct.Block.html#structfield.utxo_root).
+pub const UTXO_ROOT_ENCODING_VERSION: u8 = 5;
+
+/// A type with a canonical binary encoding.
+///
+/// The same encoding is used to compute block hashes, by the [block store](struct.BlockStore.html)
+/// and by the peer-to-peer protocol. Values are encoded as follows:
+/// * `u8`, `u32` and `u64`: fixed-width little-endian.
+/// * strings: the number of bytes as a `u32`, followed by the UTF-8 bytes.
+/// * vectors: the number of items as a `u32`, followed by the items.
+/// * [hashes](struct.BlockHash.html): their 32 bytes.
+/// * [Output](struct.Output.html): `id`, `amount`.
+/// * [UtxoEntry](struct.UtxoEntry.html): `height`, `coinbase` as a `u8`.
+/// * [Transaction](struct.Transaction.html): `inputs`, `outputs`.
+/// * [Block](struct.Block.html): the [encoding version](constant.ENCODING_VERSION.html),
+///   `difficulty`, `hash`, `nonce`, `predecessor`, `transactions`.
+/// * [BlockHeader](struct.BlockHeader.html): the encoding version, `difficulty`, `hash`, `nonce`,
+///   `predecessor`, `transactions_root`.
+///
+/// Blocks and headers with a `utxo_root` start with
+/// [another version](constant.UTXO_ROOT_ENCODING_VERSION.html) and have the root after the
+/// `predecessor` of a block, or after the `transactions_root` of a header.
+///
+/// The hash of a block is computed from its header encoded without the `hash` field.
+///
+/// # Examples
+///
+/// The test vector of a block with a single coinbase transaction:
+///
+/// ```
+/// # use mycoinlib::{Block, BlockHash, Decode, Encode, Hashable, Output, Transaction};
+/// let coinbase = Transaction {
+///     inputs: vec![],
+///     outputs: vec![Output { id: 1, amount: 50 }],
+/// };
+/// let mut block = Block::new(1, BlockHash::ZERO, 3, BlockHash::ZERO, vec![coinbase]);
+/// block.hash = block.hash();
+/// assert_eq!(
+///     block.hash.to_string(),
+///     "0x3c8a605b3bc056a67885606e530a4816121726a47a2ca38392e64a688be5cd0c"
+/// );
+///
+/// let bytes = block.encode();
+/// let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+/// assert_eq!(
+///     hex,
+///     concat!(
+///         "04",                 // encoding version
+///         "01000000",           // difficulty
+///         "3c8a605b3bc056a67885606e530a4816121726a47a2ca38392e64a688be5cd0c", // hash
+///         "0300000000000000",   // nonce
+///         "0000000000000000000000000000000000000000000000000000000000000000", // predecessor
+///         "01000000",           // one transaction
+///         "00000000",           // no inputs
+///         "01000000",           // one output
+///         "0100000000000000",   // id
+///         "3200000000000000",   // amount
+///     )
+/// );
+/// assert_eq!(Block::decode(&bytes).unwrap().hash, block.hash);
+/// ```
+pub trait Encode {
+    /// Appends the encoding of the value to the buffer.
+    fn encode_to(&self, out: &mut Vec<u8>);
+    /// Returns the encoding of the value.
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.encode_to(&mut out);
+        out
+    }
+}
+
+/// A type that can be read back from its [canonical encoding](trait.Encode.html).
+pub trait Decode: Sized {
+    /// Decodes a value from the start of the input, advancing it past the value.
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError>;
+    /// Decodes a value which must span the whole input.
+    fn decode(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut input = bytes;
+        let value = Self::decode_from(&mut input)?;
+        if !input.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(value)
+    }
+}
+
+/// Splits the first `n` bytes off the input.
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < n {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let (bytes, rest) = input.split_at(n);
+    *input = rest;
+    Ok(bytes)
+}
+
+/// Decodes the encoding version of a block or header, and returns whether it commits to a UTXO
+/// root.
+fn decode_version(input: &mut &[u8]) -> Result<bool, DecodeError> {
+    match u8::decode_from(input)? {
+        ENCODING_VERSION => Ok(false),
+        UTXO_ROOT_ENCODING_VERSION => Ok(true),
+        version => Err(DecodeError::UnsupportedVersion(version)),
+    }
+}
+
+/// Decodes a UTXO root if the encoding version says there is one.
+fn decode_utxo_root(input: &mut &[u8], present: bool) -> Result<Option<BlockHash>, DecodeError> {
+    if present {
+        Ok(Some(BlockHash::decode_from(input)?))
+    } else {
+        Ok(None)
+    }
+}
+
+impl Encode for u8 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Decode for u8 {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(take(input, 1)?[0])
+    }
+}
+
+impl Encode for u32 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u32 {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(take(input, 4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+}
+
+impl Encode for u64 {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend(&self.to_le_bytes());
+    }
+}
+
+impl Decode for u64 {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(take(input, 8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
+
+impl Encode for String {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode_to(out);
+        out.extend(self.as_bytes());
+    }
+}
+
+impl Decode for String {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = u32::decode_from(input)? as usize;
+        let bytes = take(input, len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+}
+
+impl<T: Encode> Encode for Vec<T> {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode_to(out);
+        for item in self {
+            item.encode_to(out);
+        }
+    }
+}
+
+impl<T: Decode> Decode for Vec<T> {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = u32::decode_from(input)? as usize;
+        // every item takes at least a byte, so a bogus length can't allocate more than the input
+        let mut items = Vec::with_capacity(len.min(input.len()));
+        for _ in 0..len {
+            items.push(T::decode_from(input)?);
+        }
+        Ok(items)
+    }
+}
+
+impl Encode for BlockHash {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        out.extend(&self.0);
+    }
+}
+
+impl Decode for BlockHash {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(take(input, 32)?);
+        Ok(BlockHash(bytes))
+    }
+}
+
+impl Encode for Output {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.id.encode_to(out);
+        self.amount.encode_to(out);
+    }
+}
+
+impl Decode for Output {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Output {
+            id: u64::decode_from(input)?,
+            amount: u64::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for UtxoEntry {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.height.encode_to(out);
+        (self.coinbase as u8).encode_to(out);
+    }
+}
+
+impl Decode for UtxoEntry {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(UtxoEntry {
+            height: u64::decode_from(input)?,
+            coinbase: u8::decode_from(input)? != 0,
+        })
+    }
+}
+
+impl Encode for Transaction {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.inputs.encode_to(out);
+        self.outputs.encode_to(out);
+    }
+}
+
+impl Decode for Transaction {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(Transaction {
+            inputs: Vec::decode_from(input)?,
+            outputs: Vec::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for Block {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.encoding_version().encode_to(out);
+        self.difficulty.encode_to(out);
+        self.hash.encode_to(out);
+        self.nonce.encode_to(out);
+        self.predecessor.encode_to(out);
+        if let Some(utxo_root) = &self.utxo_root {
+            utxo_root.encode_to(out);
+        }
+        self.transactions.encode_to(out);
+    }
+}
+
+impl Decode for Block {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let committed = decode_version(input)?;
+        let difficulty = u32::decode_from(input)?;
+        let hash = BlockHash::decode_from(input)?;
+        let nonce = u64::decode_from(input)?;
+        let predecessor = BlockHash::decode_from(input)?;
+        let utxo_root = decode_utxo_root(input, committed)?;
+        Ok(Block {
+            difficulty,
+            hash,
+            nonce,
+            predecessor,
+            transactions: Vec::decode_from(input)?,
+            utxo_root,
+        })
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.encoding_version().encode_to(out);
+        self.difficulty.encode_to(out);
+        self.hash.encode_to(out);
+        self.nonce.encode_to(out);
+        self.predecessor.encode_to(out);
+        self.transactions_root.encode_to(out);
+        if let Some(utxo_root) = &self.utxo_root {
+            utxo_root.encode_to(out);
+        }
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let committed = decode_version(input)?;
+        Ok(BlockHeader {
+            difficulty: u32::decode_from(input)?,
+            hash: BlockHash::decode_from(input)?,
+            nonce: u64::decode_from(input)?,
+            predecessor: BlockHash::decode_from(input)?,
+            transactions_root: BlockHash::decode_from(input)?,
+            utxo_root: decode_utxo_root(input, committed)?,
+        })
+    }
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+
+/// The reason a block was rejected by [Block::validate](struct.Block.html#method.validate),
+/// [Blockchain::submit](struct.Blockchain.html#method.submit) or the [Network](struct.Network.html),
+/// or a query to the network or a [snapshot](struct.UtxoSnapshot.html) failed.
+///
+/// The `Display` implementation returns the message printed to the user, e.g. `invalid hash`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValidationError {
+    /// The provided hash is not the computed hash of the block.
+    InvalidHash,
+    /// The difficulty is greater than the number of characters of a hash.
+    DifficultyTooHigh,
+    /// The hash does not start with as many zeroes as the difficulty requires.
+    LeadingZeroes,
+    /// A transaction spends an unknown output, spends an output twice or doesn't balance.
+    InvalidTransaction,
+    /// A block was submitted before the genesis block.
+    NotInitialized,
+    /// The predecessor of the block is unknown.
+    NoPredecessor,
+    /// The block is already known.
+    DuplicateHash,
+    /// The difficulty is lower than the difficulty of the predecessor.
+    DifficultyDecreased,
+    /// The difficulty is lower than the minimum difficulty of the chain.
+    DifficultyTooLow,
+    /// The encoded block is larger than the maximum block size of the chain.
+    BlockTooLarge,
+    /// The block has more transactions than the chain allows.
+    TooManyTransactions,
+    /// A transaction has more inputs than the chain allows.
+    TooManyInputs,
+    /// A transaction has more outputs than the chain allows.
+    TooManyOutputs,
+    /// The coinbase transactions create more than the block subsidy.
+    InvalidCoinbase,
+    /// A transaction spends the output of a coinbase transaction before it matured.
+    ImmatureCoinbase,
+    /// The genesis block is not the genesis block of the chain, or the network was already
+    /// initialized with another one.
+    InvalidGenesis,
+    /// The predecessor of the genesis block is not zero.
+    GenesisPredecessor,
+    /// A transaction of the genesis block has inputs.
+    GenesisInputs,
+    /// Blocks can't be generated on demand on this chain.
+    GenerateDisabled,
+    /// Too many blocks were asked to be generated, or their output ids overflow.
+    InvalidGenerate,
+    /// The queried block is not known to the network.
+    UnknownBlock,
+    /// The first block of a queried range is not an ancestor of the last one.
+    NotAncestor,
+    /// The queried block is not a head.
+    NotAHead,
+    /// The queried block is not in the main chain.
+    NotInMainChain,
+    /// A snapshot doesn't match its content hash or its base block, or its outputs are unsorted,
+    /// duplicated or created outside of the chain up to its base block.
+    InvalidSnapshot,
+    /// A snapshot was loaded into a network that already has blocks besides its genesis block.
+    ChainNotEmpty,
+    /// The history of the chain doesn't lead to the base block and UTXO set of a snapshot.
+    SnapshotMismatch,
+    /// The UTXO root of the block is not the root of the unspent outputs after it, or the chain
+    /// doesn't commit to the unspent outputs.
+    InvalidUtxoRoot,
+    /// A UTXO proof doesn't match the root or doesn't prove anything about the output, or a
+    /// transaction proof doesn't match the block header or the transaction.
+    InvalidProof,
+    /// The body of the queried block was pruned, only its header is known.
+    BlockPruned,
+    /// The transaction is not in the queried block.
+    UnknownTransaction,
+    /// A peer announced a message larger than it may send, see
+    /// [max_payload_size](fn.max_payload_size.html).
+    MessageTooLarge,
+}
+
+impl ValidationError {
+    /// Returns how much a peer's misbehavior score increases when it sends a block failing with
+    /// this error.
+    ///
+    /// Failures that require deliberately invalid data (a wrong hash or an unbalanced
+    /// transaction) get a peer banned at once, while those that an honest peer may cause
+    /// (e.g. relaying a block we already have) cost little or nothing.
+    pub fn misbehavior_score(&self) -> u32 {
+        match self {
+            ValidationError::InvalidHash
+            | ValidationError::DifficultyTooHigh
+            | ValidationError::LeadingZeroes
+            | ValidationError::InvalidTransaction
+            | ValidationError::DifficultyDecreased
+            | ValidationError::DifficultyTooLow
+            | ValidationError::BlockTooLarge
+            | ValidationError::TooManyTransactions
+            | ValidationError::TooManyInputs
+            | ValidationError::TooManyOutputs
+            | ValidationError::InvalidCoinbase
+            | ValidationError::ImmatureCoinbase
+            | ValidationError::InvalidGenesis
+            | ValidationError::GenesisPredecessor
+            | ValidationError::GenesisInputs
+            | ValidationError::InvalidUtxoRoot
+            | ValidationError::MessageTooLarge => 100,
+            ValidationError::NoPredecessor => 10,
+            ValidationError::NotInitialized
+            | ValidationError::DuplicateHash
+            | ValidationError::GenerateDisabled
+            | ValidationError::InvalidGenerate
+            | ValidationError::UnknownBlock
+            | ValidationError::NotAncestor
+            | ValidationError::NotAHead
+            | ValidationError::NotInMainChain
+            | ValidationError::InvalidSnapshot
+            | ValidationError::ChainNotEmpty
+            | ValidationError::SnapshotMismatch
+            | ValidationError::InvalidProof
+            | ValidationError::BlockPruned
+            | ValidationError::UnknownTransaction => 0,
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            ValidationError::InvalidHash => "invalid hash",
+            ValidationError::DifficultyTooHigh => "maximum value of difficulty is 64",
+            ValidationError::LeadingZeroes => {
+                "leading zeroes in block hash did not match difficulty"
+            }
+            ValidationError::InvalidTransaction => "invalid transaction",
+            ValidationError::NotInitialized => "must initialize first",
+            ValidationError::NoPredecessor => "no predecessor found",
+            ValidationError::DuplicateHash => "duplicate hash",
+            ValidationError::DifficultyDecreased => "difficulty must not decrease",
+            ValidationError::DifficultyTooLow => "difficulty is below the minimum difficulty",
+            ValidationError::BlockTooLarge => "block exceeds the maximum block size",
+            ValidationError::TooManyTransactions => "too many transactions in block",
+            ValidationError::TooManyInputs => "too many inputs in transaction",
+            ValidationError::TooManyOutputs => "too many outputs in transaction",
+            ValidationError::InvalidCoinbase => "coinbase exceeds the block subsidy",
+            ValidationError::ImmatureCoinbase => "coinbase output spent before maturity",
+            ValidationError::InvalidGenesis => "genesis block does not match the chain",
+            ValidationError::GenesisPredecessor => "genesis block must not have a predecessor",
+            ValidationError::GenesisInputs => "genesis transactions must not have inputs",
+            ValidationError::GenerateDisabled => "generate is not available on this chain",
+            ValidationError::InvalidGenerate => "too many blocks or output ids out of range",
+            ValidationError::UnknownBlock => "unknown block",
+            ValidationError::NotAncestor => "block is not an ancestor",
+            ValidationError::NotAHead => "block is not a head",
+            ValidationError::NotInMainChain => "block is not in the main chain",
+            ValidationError::InvalidSnapshot => "invalid snapshot",
+            ValidationError::ChainNotEmpty => "the chain already has blocks",
+            ValidationError::SnapshotMismatch => "snapshot does not match the chain history",
+            ValidationError::InvalidUtxoRoot => "UTXO root does not match the unspent outputs",
+            ValidationError::InvalidProof => "invalid proof",
+            ValidationError::BlockPruned => "block body was pruned",
+            ValidationError::UnknownTransaction => "transaction is not in the block",
+            ValidationError::MessageTooLarge => "message exceeds the maximum payload size",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// The reason bytes could not be [decoded](trait.Decode.html).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DecodeError {
+    /// The input ended in the middle of a value.
+    UnexpectedEnd,
+    /// The encoding version is not supported by this client.
+    UnsupportedVersion(u8),
+    /// A string is not valid UTF-8.
+    InvalidUtf8,
+    /// The input continues after the decoded value.
+    TrailingBytes,
+    /// A [hash](struct.BlockHash.html) is not `0x` followed by 64 lowercase hexadecimal digits.
+    InvalidHash,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => f.write_str("unexpected end of input"),
+            DecodeError::UnsupportedVersion(v) => write!(f, "unsupported encoding version {}", v),
+            DecodeError::InvalidUtf8 => f.write_str("invalid utf-8 string"),
+            DecodeError::TrailingBytes => f.write_str("trailing bytes after value"),
+            DecodeError::InvalidHash => f.write_str("invalid hash"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
@@ -0,0 +1,95 @@
+use super::DecodeError;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// A 32-byte hash, such as the hash of a [block](struct.Block.html).
+///
+/// Its text form, used in JSON, is `0x` followed by exactly 64 lowercase hexadecimal digits;
+/// anything else is rejected when parsing.
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::BlockHash;
+/// let text = "0x00f0000000000000000000000000000000000000000000000000000000000001";
+/// let hash: BlockHash = text.parse().unwrap();
+/// assert_eq!(hash.to_string(), text);
+/// assert_eq!(hash.leading_zero_digits(), 2);
+///
+/// assert!("0x00f0".parse::<BlockHash>().is_err());
+/// assert!(text.to_uppercase().parse::<BlockHash>().is_err());
+/// assert!(serde_json::from_str::<BlockHash>("\"\"").is_err());
+/// ```
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockHash(pub [u8; 32]);
+
+impl BlockHash {
+    /// The hash made of zeroes, used as the predecessor of a genesis block.
+    pub const ZERO: BlockHash = BlockHash([0; 32]);
+
+    /// Returns the number of leading zero hexadecimal digits of the hash.
+    pub fn leading_zero_digits(&self) -> u32 {
+        let mut digits = 0;
+        for byte in &self.0 {
+            if *byte != 0 {
+                if *byte < 0x10 {
+                    digits += 1;
+                }
+                break;
+            }
+            digits += 2;
+        }
+        digits
+    }
+}
+
+impl fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("0x")?;
+        for byte in &self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for BlockHash {
+    type Err = DecodeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = match s.strip_prefix("0x") {
+            Some(digits) if digits.len() == 64 => digits.as_bytes(),
+            _ => return Err(DecodeError::InvalidHash),
+        };
+        let digit = |c: u8| match c {
+            b'0'..=b'9' => Ok(c - b'0'),
+            b'a'..=b'f' => Ok(c - b'a' + 10),
+            _ => Err(DecodeError::InvalidHash),
+        };
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = digit(digits[2 * i])? << 4 | digit(digits[2 * i + 1])?;
+        }
+        Ok(BlockHash(bytes))
+    }
+}
+
+impl Serialize for BlockHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
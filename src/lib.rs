@@ -11,22 +11,68 @@ pub trait Hashable {
     fn bytes(&self) -> Vec<u8>;
     /// Returns the hash of a Hashable.
     ///
-    /// For a block, it is the 0x-prefixed lowercase-base16-encoded
-    /// SHA256 hash of the JSON-encoded tuple of the predecessor hash, the list of
-    /// transactions, the difficulty and the nonce.
-    fn hash(&self) -> String {
-        format!(
-            "0x{}",
-            crypto_hash::hex_digest(crypto_hash::Algorithm::SHA256, &self.bytes())
-        )
+    /// For a block, it is the SHA256 hash of the [canonical encoding](trait.Encode.html) of the
+    /// encoding version, the difficulty, the nonce, the predecessor hash and the
+    /// [transactions root](struct.Block.html#method.transactions_root).
+    fn hash(&self) -> BlockHash {
+        self.hash_with(&Sha256)
+    }
+    /// Returns the hash of a Hashable computed with the given
+    /// [proof-of-work algorithm](trait.PowHasher.html) instead of SHA256.
+    fn hash_with(&self, hasher: &dyn PowHasher) -> BlockHash {
+        BlockHash(hasher.digest(&self.bytes()))
     }
 }
 
 mod block;
-pub use crate::block::Block;
+pub use crate::block::{Block, BlockHeader};
 mod blockchain;
-pub use crate::blockchain::{Blockchain, InitGenesis, SubmittedBlock};
+pub use crate::blockchain::{
+    Blockchain, ChainQuery, GenerateBlocks, InitGenesis, SubmittedBlock, TreeFormat,
+};
+mod clock;
+pub use crate::clock::{Clock, ManualClock, SystemClock};
+mod encoding;
+pub use crate::encoding::{Decode, Encode, ENCODING_VERSION, UTXO_ROOT_ENCODING_VERSION};
+mod error;
+pub use crate::error::{DecodeError, ValidationError};
+mod hash;
+pub use crate::hash::BlockHash;
+mod light;
+pub use crate::light::{Confirmation, IndexedHeader, LightClient};
+mod listener;
+pub use crate::listener::ChainListener;
 mod network;
-pub use crate::network::{ChainState, Head, Network};
+pub use crate::network::{
+    ChainState, DoubleSpend, Head, HeadComparison, IndexedBlock, Network, RecentBlock, MAX_GENERATE,
+};
+mod p2p;
+pub use crate::p2p::{
+    max_payload_size, GetHeadersPayload, Message, Node, VersionPayload, MAX_HANDSHAKE_PAYLOAD_SIZE,
+    PROTOCOL_VERSION, READ_TIMEOUT, WRITE_TIMEOUT,
+};
+mod params;
+pub use crate::params::{ChainParams, Subsidy};
+mod peers;
+pub use crate::peers::{
+    BanList, PeerInfo, BAN_DURATION, BAN_SCORE, MAX_INBOUND_PEERS, MAX_OUTBOUND_PEERS,
+};
+mod pow;
+pub use crate::pow::{pow_hasher, Blake3, DoubleSha256, PowHasher, Sha256};
+mod simulator;
+pub use crate::simulator::{SimConfig, SimNode, SimReport, SimRng, Simulator};
+mod snapshot;
+pub use crate::snapshot::{SnapshotBase, UtxoSnapshot};
+mod store;
+pub use crate::store::{BlockStore, StoredBlock, StoredBlocks};
+mod sync;
+pub use crate::sync::{HeaderSync, BLOCK_REQUEST_TIMEOUT, MAX_BLOCKS_IN_FLIGHT, MAX_HEADERS};
 mod transaction;
-pub use crate::transaction::{Output, Transaction};
+pub use crate::transaction::{Output, Transaction, TransactionProof};
+mod utxo;
+pub use crate::utxo::{
+    UtxoDiff, UtxoEntry, UtxoFilter, UtxoLeaf, UtxoPage, UtxoProof, UtxoSet, UtxoUndo,
+    UTXO_PAGE_SIZE,
+};
+mod wallet;
+pub use crate::wallet::{Balance, Wallet, WalletChanges, WalletKey, MIN_CONFIRMATIONS};
@@ -0,0 +1,386 @@
+use super::{
+    BlockHash, BlockHeader, ChainParams, ChainState, Clock, Head, Output, PowHasher, Transaction,
+    TransactionProof, UtxoEntry, UtxoProof, ValidationError,
+};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// A struct that represents a block header known to a [light client](struct.LightClient.html),
+/// with the data computed when it was accepted.
+#[derive(Clone, Debug)]
+pub struct IndexedHeader {
+    pub header: BlockHeader,
+    /// The height of the block, the genesis block being at height 1.
+    pub height: u64,
+    /// The totalWork of the chain ending with the block.
+    pub total_work: u64,
+    /// The time the header was received at, from the clock of the light client.
+    pub received_at: u128,
+}
+
+/// A struct that tells how deep a block is buried in the main chain of a
+/// [light client](struct.LightClient.html).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Confirmation {
+    pub block: BlockHash,
+    pub height: u64,
+    /// The number of blocks of the main chain from the block to the head, both included.
+    pub confirmations: u64,
+    /// The work of these blocks, which a competing branch would have to redo to replace the
+    /// block.
+    pub work: u64,
+}
+
+/// A struct that follows a chain from its block headers alone, without any transaction or
+/// unspent output.
+///
+/// Headers are validated (linkage, proof of work and difficulty) and the main chain is chosen
+/// among the heads with the same [fork choice rule](struct.Network.html#method.get_main_chain)
+/// as the [Network](struct.Network.html). A full node then proves to the light client that a
+/// transaction is in a block with [Network::prove_transaction](struct.Network.html#method.prove_transaction),
+/// or that an output is unspent with [Network::prove_utxo](struct.Network.html#method.prove_utxo),
+/// and the light client checks how much work buries the block.
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::{
+/// #     Block, BlockHash, Hashable, LightClient, Network, Output, SystemClock, Transaction,
+/// #     MAX_HEADERS,
+/// # };
+/// let coin = Output { id: 1, amount: 50 };
+/// let funding = Transaction {
+///     inputs: vec![],
+///     outputs: vec![coin],
+/// };
+/// let mut network = Network::new(2);
+/// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![funding]);
+/// genesis.hash = genesis.hash();
+/// network.init(genesis.clone()).unwrap();
+/// network.generate(1, 100, None).unwrap();
+///
+/// let payment = Transaction {
+///     inputs: vec![coin],
+///     outputs: vec![Output { id: 2, amount: 50 }],
+/// };
+/// let mut block = Block::new(0, BlockHash::ZERO, 0, network.state.hash, vec![payment.clone()]);
+/// block.hash = block.hash();
+/// network.submit(block.clone()).unwrap();
+/// network.generate(2, 300, None).unwrap();
+///
+/// let mut light = LightClient::with_params(network.params.clone(), Box::new(SystemClock));
+/// light.init(genesis.header()).unwrap();
+/// let headers = network.headers_after(&light.locator(), MAX_HEADERS);
+/// assert_eq!(light.submit_headers(headers), Ok(4));
+/// assert_eq!(light.state.hash, network.state.hash);
+///
+/// let proof = network.prove_transaction(&block.hash, &payment).unwrap();
+/// let confirmation = light.verify_transaction(&payment, &proof).unwrap();
+/// assert_eq!(confirmation.height, 3);
+/// assert_eq!(confirmation.confirmations, 3);
+///
+/// let mut forged = payment.clone();
+/// forged.outputs[0].amount += 1;
+/// assert!(light.verify_transaction(&forged, &proof).is_err());
+/// ```
+pub struct LightClient {
+    /// It maps the hash of every accepted header, in the main chain or in a fork, with its
+    /// height, totalWork and receipt time.
+    pub headers: HashMap<BlockHash, IndexedHeader>,
+    /// It stores the head of each of the possible forks.
+    pub heads: HashSet<Head>,
+    /// The hashes of the headers of the main chain, in chain order.
+    pub main_chain: Vec<BlockHash>,
+    /// The current state of the main chain.
+    pub state: ChainState,
+    /// The clock used to timestamp received headers.
+    pub clock: Box<dyn Clock>,
+    /// The proof-of-work algorithm of the [chain parameters](#structfield.params).
+    pub pow: Arc<dyn PowHasher>,
+    /// The consensus rules of the chain.
+    pub params: ChainParams,
+}
+
+impl LightClient {
+    /// Creates a new `LightClient` instance following the given
+    /// [chain parameters](struct.ChainParams.html).
+    ///
+    /// If the parameters have a genesis block, the light client is initialized with its header.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the proof-of-work algorithm of the parameters is unknown.
+    pub fn with_params(params: ChainParams, clock: Box<dyn Clock>) -> Self {
+        let pow = params.hasher();
+        let genesis = params.genesis.as_ref().map(|block| block.header());
+        let mut light = LightClient {
+            headers: HashMap::new(),
+            heads: HashSet::new(),
+            main_chain: vec![],
+            state: ChainState {
+                height: 0,
+                totalWork: 0,
+                hash: BlockHash::ZERO,
+            },
+            clock,
+            pow,
+            params,
+        };
+        if let Some(genesis) = genesis {
+            light.init(genesis).expect("invalid genesis block");
+        }
+        light
+    }
+    /// Accepts the header of the genesis block.
+    ///
+    /// Like the [Network](struct.Network.html#method.init), the light client is bound to a
+    /// single genesis block: the one of its chain parameters if any, otherwise the first one
+    /// initialized.
+    ///
+    /// Returns an error if the header was not accepted.
+    pub fn init(&mut self, header: BlockHeader) -> Result<(), ValidationError> {
+        if let Some(genesis) = self.main_chain.first() {
+            if *genesis == header.hash {
+                return Err(ValidationError::DuplicateHash);
+            }
+            return Err(ValidationError::InvalidGenesis);
+        }
+        if let Some(genesis) = &self.params.genesis {
+            if genesis.hash != header.hash {
+                return Err(ValidationError::InvalidGenesis);
+            }
+        }
+        if header.predecessor != BlockHash::ZERO {
+            return Err(ValidationError::GenesisPredecessor);
+        }
+        self.check_header(&header)?;
+        let hash = header.hash;
+        let total_work = u64::pow(16, header.difficulty);
+        self.headers.insert(
+            hash,
+            IndexedHeader {
+                header,
+                height: 1,
+                total_work,
+                received_at: self.clock.now(),
+            },
+        );
+        self.heads.insert(Head {
+            height: 1,
+            totalWork: total_work,
+            hash,
+        });
+        self.main_chain = vec![hash];
+        self.state = ChainState {
+            height: 1,
+            totalWork: total_work,
+            hash,
+        };
+        Ok(())
+    }
+    /// Checks the proof of work, the difficulty and the UTXO commitment of a header against the
+    /// [chain parameters](struct.ChainParams.html). The size limits can't be checked without
+    /// the transactions.
+    fn check_header(&self, header: &BlockHeader) -> Result<(), ValidationError> {
+        header.validate_with(&*self.pow)?;
+        if header.difficulty < self.params.min_difficulty {
+            return Err(ValidationError::DifficultyTooLow);
+        }
+        if header.utxo_root.is_some() != self.params.utxo_commitment {
+            return Err(ValidationError::InvalidUtxoRoot);
+        }
+        Ok(())
+    }
+    /// Submits a new header, following a known one.
+    ///
+    /// Returns an error if the header was not accepted.
+    ///
+    /// # Examples
+    ///
+    /// A fork with more work becomes the main chain, as in the [Network](struct.Network.html):
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, LightClient, Network, SystemClock};
+    /// let mut network = Network::new(2);
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// network.init(genesis.clone()).unwrap();
+    /// let mut light = LightClient::with_params(network.params.clone(), Box::new(SystemClock));
+    /// light.init(genesis.header()).unwrap();
+    ///
+    /// let main = network.generate(2, 100, None).unwrap();
+    /// let fork = network.generate(3, 200, Some(&genesis.hash)).unwrap();
+    /// for hash in main.iter().chain(&fork) {
+    ///     light.submit(network.indexed_block(hash).unwrap().header.clone()).unwrap();
+    /// }
+    /// assert_eq!(light.state.hash, network.state.hash);
+    /// assert_eq!(light.heads, network.heads);
+    /// assert_eq!(light.confirmation(&fork[0]).unwrap().confirmations, 3);
+    /// assert!(light.confirmation(&main[0]).is_err());
+    /// ```
+    pub fn submit(&mut self, header: BlockHeader) -> Result<(), ValidationError> {
+        if self.heads.is_empty() {
+            return Err(ValidationError::NotInitialized);
+        }
+        self.check_header(&header)?;
+        let predecessor = match self.headers.get(&header.predecessor) {
+            Some(predecessor) => predecessor,
+            None => return Err(ValidationError::NoPredecessor),
+        };
+        if self.headers.contains_key(&header.hash) {
+            return Err(ValidationError::DuplicateHash);
+        }
+        if predecessor.header.difficulty > header.difficulty {
+            return Err(ValidationError::DifficultyDecreased);
+        }
+        let head = Head {
+            height: predecessor.height + 1,
+            totalWork: predecessor.total_work + u64::pow(16, header.difficulty),
+            hash: header.hash,
+        };
+        self.heads.remove(&Head {
+            height: predecessor.height,
+            totalWork: predecessor.total_work,
+            hash: header.predecessor,
+        });
+        self.heads.insert(head.clone());
+        let received_at = self.clock.now();
+        let extends_main_chain = header.predecessor == self.state.hash;
+        self.headers.insert(
+            head.hash,
+            IndexedHeader {
+                header,
+                height: head.height,
+                total_work: head.totalWork,
+                received_at,
+            },
+        );
+
+        let main_head = Head {
+            height: self.state.height,
+            totalWork: self.state.totalWork,
+            hash: self.state.hash,
+        };
+        if extends_main_chain {
+            self.main_chain.push(head.hash);
+        } else if head.wins_over(
+            received_at,
+            &main_head,
+            self.headers[&main_head.hash].received_at,
+        ) {
+            self.reorganize(&head.hash);
+        } else {
+            return Ok(());
+        }
+        self.state = ChainState {
+            height: head.height,
+            totalWork: head.totalWork,
+            hash: head.hash,
+        };
+        Ok(())
+    }
+    /// Submits headers in chain order, e.g. the headers a full node returns for the
+    /// [locator](#method.locator) of the light client, skipping those already known.
+    ///
+    /// Returns the number of new headers, or the reason the first invalid header was rejected.
+    pub fn submit_headers(&mut self, headers: Vec<BlockHeader>) -> Result<usize, ValidationError> {
+        let mut added = 0;
+        for header in headers {
+            match self.submit(header) {
+                Ok(()) => added += 1,
+                Err(ValidationError::DuplicateHash) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(added)
+    }
+    /// Makes the known header with the given hash the head of the main chain.
+    fn reorganize(&mut self, hash: &BlockHash) {
+        let mut branch = vec![];
+        let mut indexed = &self.headers[hash];
+        while !self.in_main_chain(indexed) {
+            branch.push(indexed.header.hash);
+            indexed = &self.headers[&indexed.header.predecessor];
+        }
+        self.main_chain.truncate(indexed.height as usize);
+        self.main_chain.extend(branch.into_iter().rev());
+    }
+    /// Returns `true` if the header is in the main chain.
+    fn in_main_chain(&self, indexed: &IndexedHeader) -> bool {
+        self.main_chain.get(indexed.height as usize - 1) == Some(&indexed.header.hash)
+    }
+    /// Returns the header with the given hash from the main chain or any of the forks, with its
+    /// height, totalWork and receipt time.
+    pub fn header(&self, hash: &BlockHash) -> Result<&IndexedHeader, ValidationError> {
+        self.headers.get(hash).ok_or(ValidationError::UnknownBlock)
+    }
+    /// Returns a block locator of the main chain, see
+    /// [Network::locator](struct.Network.html#method.locator).
+    pub fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = vec![];
+        let mut step = 1;
+        let mut height = self.main_chain.len();
+        while height > 0 {
+            locator.push(self.main_chain[height - 1]);
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        if let Some(genesis) = self.main_chain.first() {
+            if locator.last() != Some(genesis) {
+                locator.push(*genesis);
+            }
+        }
+        locator
+    }
+    /// Returns how deep the block with the given hash is buried in the main chain.
+    ///
+    /// Returns an error if the block is unknown or not in the main chain.
+    pub fn confirmation(&self, hash: &BlockHash) -> Result<Confirmation, ValidationError> {
+        let indexed = self.header(hash)?;
+        if !self.in_main_chain(indexed) {
+            return Err(ValidationError::NotInMainChain);
+        }
+        Ok(Confirmation {
+            block: *hash,
+            height: indexed.height,
+            confirmations: self.state.height - indexed.height + 1,
+            work: self.state.totalWork - indexed.total_work
+                + u64::pow(16, indexed.header.difficulty),
+        })
+    }
+    /// Verifies that the transaction is in a block of the main chain with a
+    /// [proof](struct.TransactionProof.html) from a full node, and returns how deep the block is
+    /// buried.
+    ///
+    /// Returns an error if the block is unknown or not in the main chain, or if the proof
+    /// doesn't match its header or the transaction.
+    pub fn verify_transaction(
+        &self,
+        transaction: &Transaction,
+        proof: &TransactionProof,
+    ) -> Result<Confirmation, ValidationError> {
+        proof.verify(transaction, &self.header(&proof.block)?.header)?;
+        self.confirmation(&proof.block)
+    }
+    /// Verifies a [UTXO proof](struct.UtxoProof.html) from a full node against the
+    /// [UTXO root](struct.BlockHeader.html#structfield.utxo_root) of the given block, see
+    /// [UtxoProof::verify](struct.UtxoProof.html#method.verify).
+    ///
+    /// Returns an error if the block is unknown or doesn't commit to a UTXO root, or if the
+    /// proof is invalid.
+    pub fn verify_utxo(
+        &self,
+        hash: &BlockHash,
+        output: &Output,
+        proof: &UtxoProof,
+    ) -> Result<Option<UtxoEntry>, ValidationError> {
+        let root = self
+            .header(hash)?
+            .header
+            .utxo_root
+            .ok_or(ValidationError::InvalidUtxoRoot)?;
+        proof.verify(output, &root)
+    }
+}
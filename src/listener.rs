@@ -0,0 +1,55 @@
+use super::IndexedBlock;
+use std::sync::{Arc, Mutex};
+
+/// An observer of the main chain of a [Network](struct.Network.html), told about every block
+/// connected to it or disconnected from it, see
+/// [Network::listeners](struct.Network.html#structfield.listeners).
+///
+/// A reorganization disconnects the blocks of the former branch from its head down, then
+/// connects those of the new branch in chain order. The [changes](struct.UtxoUndo.html) of a
+/// block to the UTXO set are empty if it was [pruned](struct.Network.html#method.prune).
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::{Block, BlockHash, ChainListener, Hashable, IndexedBlock, Network};
+/// # use std::sync::{Arc, Mutex};
+/// #[derive(Default)]
+/// struct Heights(Vec<i64>);
+///
+/// impl ChainListener for Heights {
+///     fn connected(&mut self, block: &IndexedBlock) {
+///         self.0.push(block.height as i64);
+///     }
+///     fn disconnected(&mut self, block: &IndexedBlock) {
+///         self.0.push(-(block.height as i64));
+///     }
+/// }
+///
+/// let heights = Arc::new(Mutex::new(Heights::default()));
+/// let mut network = Network::new(2);
+/// network.listeners.push(Box::new(heights.clone()));
+/// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+/// genesis.hash = genesis.hash();
+/// network.init(genesis.clone()).unwrap();
+///
+/// network.generate(1, 100, None).unwrap();
+/// network.generate(2, 200, Some(&genesis.hash)).unwrap();
+/// assert_eq!(heights.lock().unwrap().0, vec![1, 2, -2, 2, 3]);
+/// ```
+pub trait ChainListener: Send {
+    /// Called when the block becomes the head of the main chain.
+    fn connected(&mut self, block: &IndexedBlock);
+    /// Called when the block, the head of the main chain, is disconnected from it.
+    fn disconnected(&mut self, block: &IndexedBlock);
+}
+
+/// A listener shared with other threads, locked to be told about each block.
+impl<L: ChainListener> ChainListener for Arc<Mutex<L>> {
+    fn connected(&mut self, block: &IndexedBlock) {
+        self.lock().unwrap().connected(block);
+    }
+    fn disconnected(&mut self, block: &IndexedBlock) {
+        self.lock().unwrap().disconnected(block);
+    }
+}
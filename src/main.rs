@@ -1,14 +1,217 @@
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
-use serde_json::{Result, Value};
+use serde_json::{json, Result, Value};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Read};
+use std::path::PathBuf;
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
-use mycoinlib::{Block, InitGenesis, Network, SubmittedBlock};
+use mycoinlib::{
+    BanList, Block, BlockHash, ChainParams, ChainQuery, GenerateBlocks, InitGenesis, Network, Node,
+    SnapshotBase, SubmittedBlock, SystemClock, TreeFormat, UtxoFilter, UtxoSnapshot,
+    ValidationError, Wallet, MIN_CONFIRMATIONS,
+};
 
-fn init_chain(d: &str, network: &mut Network) -> Result<()> {
-    let b: InitGenesis = match serde_json::from_str(d) {
-        Ok(b) => b,
-        Err(e) => return Err(e),
+const USAGE: &str = "\
+usage: mycoin [options] [init <file|-> | submit <file|-> | query <query> | tree <dot|json>
+                         | snapshot export <hash|height> | snapshot load <file|->
+                         | wallet <new|keys|balance|utxos>]
+
+Without a command, mycoin starts an interactive prompt.
+
+queries:
+    state, heads, peers, print
+    block <hash|height>
+    ancestors <hash> [count]
+    common_ancestor <hash> <hash>
+    range <from hash> <to hash>
+    state_at <hash|height>
+    diff <from hash> <to hash>
+    compare_heads <head hash> <head hash>
+    prove <id>:<amount> [hash]
+    prove_transaction <hash> <id>:<amount>
+    tree [dot|json]
+    utxos [head=<hash>] [min_id=<n>] [max_id=<n>] [min_amount=<n>] [max_amount=<n>]
+          [min_height=<n>] [max_height=<n>] [after=<id>:<amount>] [limit=<n>]
+
+tree prints the tree of all known blocks as a Graphviz DOT graph or as JSON.
+
+snapshot export prints a snapshot of the unspent outputs of the main chain as of a block.
+snapshot load starts the chain of the data directory from a snapshot, trusting its base block
+and validating only the blocks following it. It needs --datadir.
+
+wallet new prints the id to pay to a new key of the wallet of the data directory, to give out
+for a single payment since same-amount payments to one id count once, wallet keys
+the ids of all its keys, wallet balance its confirmed and unconfirmed balance in the main chain,
+and wallet utxos its unspent outputs. Without --datadir, the keys are lost on exit; otherwise
+the wallet file holds their secrets.
+
+options:
+    --network <chain>   main, test, regtest or the path of a TOML chain parameters file
+                        (default: main)
+    --datadir <dir>     keep the blocks, ban list and wallet of each chain in the directory,
+                        restoring them on the next run (default: nothing is kept)
+    --batch <file|->    run newline-delimited JSON commands, printing one JSON response each
+    --listen <addr>     accept peers on the address
+    --connect <addr>    connect to a peer, can be repeated
+    --verify-history <file>
+                        back-validate the snapshot the chain was started from against the
+                        blocks of a block store, in the background
+    --prune <depth>     discard from memory the bodies of blocks more than depth blocks below
+                        the main chain head, keeping at least the max reorg depth of the chain;
+                        the block store still keeps every block";
+
+/// What the client does once the network is loaded.
+enum Mode {
+    Interactive,
+    Batch(String),
+    Init(String),
+    Submit(String),
+    Query(Value),
+    Tree(TreeFormat),
+    ExportSnapshot(String),
+    LoadSnapshot(String),
+}
+
+/// Options given on the command line.
+struct Args {
+    mode: Mode,
+    network: String,
+    datadir: Option<PathBuf>,
+    listen: Option<String>,
+    connect: Vec<String>,
+    verify_history: Option<PathBuf>,
+    prune: Option<u64>,
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        mode: Mode::Interactive,
+        network: String::from("main"),
+        datadir: None,
+        listen: None,
+        connect: vec![],
+        verify_history: None,
+        prune: None,
     };
+    let mut batch = None;
+    let mut positional = vec![];
+    let mut it = env::args().skip(1);
+    while let Some(arg) = it.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg);
+            continue;
+        }
+        match (arg.as_str(), it.next()) {
+            ("--datadir", Some(dir)) => args.datadir = Some(PathBuf::from(dir)),
+            ("--network", Some(network)) => args.network = network,
+            ("--batch", Some(path)) => batch = Some(path),
+            ("--listen", Some(addr)) => args.listen = Some(addr),
+            ("--connect", Some(addr)) => args.connect.push(addr),
+            ("--verify-history", Some(path)) => args.verify_history = Some(PathBuf::from(path)),
+            ("--prune", Some(depth)) => match depth.parse() {
+                Ok(depth) => args.prune = Some(depth),
+                Err(_) => usage(),
+            },
+            _ => usage(),
+        }
+    }
+    let positional: Vec<&str> = positional.iter().map(|s| s.as_str()).collect();
+    args.mode = match (positional.as_slice(), batch) {
+        ([], None) => Mode::Interactive,
+        ([], Some(path)) => Mode::Batch(path),
+        (["init", path], None) => Mode::Init(path.to_string()),
+        (["submit", path], None) => Mode::Submit(path.to_string()),
+        (["query", query @ ..], None) => {
+            Mode::Query(query_command(query).unwrap_or_else(|| usage()))
+        }
+        (["tree", "dot"], None) => Mode::Tree(TreeFormat::Dot),
+        (["tree", "json"], None) => Mode::Tree(TreeFormat::Json),
+        (["snapshot", "export", block], None) => Mode::ExportSnapshot(block.to_string()),
+        (["snapshot", "load", path], None) => Mode::LoadSnapshot(path.to_string()),
+        (["wallet", action], None) => Mode::Query(json!({ "wallet": action })),
+        _ => usage(),
+    };
+    args
+}
+
+/// Returns the JSON command of a query given on the command line.
+fn query_command(args: &[&str]) -> Option<Value> {
+    Some(match args {
+        [field] => json!({ "query": field }),
+        ["block", block] => match block.parse::<u64>() {
+            Ok(height) => json!({ "query": "block", "height": height }),
+            Err(_) => json!({ "query": "block", "hash": block }),
+        },
+        ["ancestors", hash] => json!({ "query": "ancestors", "hash": hash }),
+        ["ancestors", hash, count] => {
+            json!({ "query": "ancestors", "hash": hash, "count": count.parse::<usize>().ok()? })
+        }
+        ["common_ancestor", a, b] => json!({ "query": "common_ancestor", "hashes": [a, b] }),
+        ["range", from, to] => json!({ "query": "range", "from": from, "to": to }),
+        ["state_at", block] => match block.parse::<u64>() {
+            Ok(height) => json!({ "query": "state_at", "height": height }),
+            Err(_) => json!({ "query": "state_at", "hash": block }),
+        },
+        ["diff", from, to] => json!({ "query": "diff", "from": from, "to": to }),
+        ["compare_heads", a, b] => json!({ "query": "compare_heads", "heads": [a, b] }),
+        ["prove", output, hash @ ..] if hash.len() <= 1 => {
+            let (id, amount) = output.split_once(':')?;
+            let output =
+                json!({ "id": id.parse::<u64>().ok()?, "amount": amount.parse::<u64>().ok()? });
+            let mut command = json!({ "query": "prove", "output": output });
+            if let [hash] = hash {
+                command["hash"] = json!(hash);
+            }
+            command
+        }
+        ["prove_transaction", hash, output] => {
+            let (id, amount) = output.split_once(':')?;
+            let output =
+                json!({ "id": id.parse::<u64>().ok()?, "amount": amount.parse::<u64>().ok()? });
+            json!({ "query": "prove_transaction", "hash": hash, "output": output })
+        }
+        ["tree", format] => json!({ "query": "tree", "format": format }),
+        ["utxos", options @ ..] => {
+            let mut command = json!({ "query": "utxos" });
+            for option in options {
+                let (key, value) = option.split_once('=')?;
+                command[key] = match (key, value.split_once(':')) {
+                    ("after", Some((id, amount))) => {
+                        json!({ "id": id.parse::<u64>().ok()?, "amount": amount.parse::<u64>().ok()? })
+                    }
+                    ("head", _) => json!(value),
+                    _ => json!(value.parse::<u64>().ok()?),
+                };
+            }
+            command
+        }
+        _ => return None,
+    })
+}
+
+/// Returns `{"ok":[]}` or the validation error.
+fn response(result: std::result::Result<(), ValidationError>) -> Value {
+    match result {
+        Ok(()) => json!({ "ok": [] }),
+        Err(e) => json!({ "error": e.to_string() }),
+    }
+}
+
+fn invalid_command() -> Value {
+    json!({ "error": "invalid command" })
+}
+
+fn init_chain(d: &str, network: &mut Network) -> Result<Value> {
+    let b: InitGenesis = serde_json::from_str(d)?;
     let mut block = Block::new(
         b.init.difficulty,
         b.init.hash,
@@ -16,17 +219,12 @@ fn init_chain(d: &str, network: &mut Network) -> Result<()> {
         b.init.predecessor,
         b.init.transactions,
     );
-    if block.validate() {
-        let _ = network.init(block);
-    }
-    Ok(())
+    block.utxo_root = b.init.utxo_root;
+    Ok(response(network.init(block)))
 }
 
-fn submit_block(d: &str, network: &mut Network) -> Result<()> {
-    let b: SubmittedBlock = match serde_json::from_str(d) {
-        Ok(b) => b,
-        Err(e) => return Err(e),
-    };
+fn submit_block(d: &str, node: &Node) -> Result<Value> {
+    let b: SubmittedBlock = serde_json::from_str(d)?;
     let mut block = Block::new(
         b.block.difficulty,
         b.block.hash,
@@ -34,48 +232,204 @@ fn submit_block(d: &str, network: &mut Network) -> Result<()> {
         b.block.predecessor,
         b.block.transactions,
     );
-    if block.validate() {
-        let _ = network.submit(block);
+    block.utxo_root = b.block.utxo_root;
+    Ok(response(node.submit(block)))
+}
+
+fn generate(d: &str, node: &Node) -> Result<Value> {
+    let g: GenerateBlocks = serde_json::from_str(d)?;
+    Ok(match node.generate(g.generate, g.to, g.on.as_ref()) {
+        Ok(hashes) => json!({ "ok": hashes }),
+        Err((hashes, e)) if !hashes.is_empty() => json!({ "error": e.to_string(), "ok": hashes }),
+        Err((_, e)) => json!({ "error": e.to_string() }),
+    })
+}
+
+fn query(d: &str, field: &Value, node: &Node) -> Result<Value> {
+    if field == "peers" {
+        return Ok(json!({ "peers": node.peer_info() }));
     }
-    Ok(())
+    let network = node.network().lock().unwrap();
+    let result = if field == "state" {
+        network.state_json()
+    } else if field == "heads" {
+        network.heads_json()
+    } else if field == "print" {
+        Ok(network.details_json())
+    } else {
+        let q: ChainQuery = serde_json::from_str(d)?;
+        network.query_json(&q)
+    };
+    Ok(result.unwrap_or_else(|e| json!({ "error": e.to_string() })))
 }
 
-fn handle_commands(data: &str, network: &mut Network) -> Result<()> {
-    let val: Value = serde_json::from_str(data)?;
-    if let Some(_) = val.get("init") {
-        let icv = init_chain(data, network);
-        if icv.is_ok() {
-            return icv;
-        } else {
-            println!("{{\"error\":\"invalid command\"}}");
-        }
+/// Runs a wallet command, after checking it and bringing the wallet up to date with the main
+/// chain.
+fn wallet_command(action: &Value, val: &Value, node: &Node, wallet: &Mutex<Wallet>) -> Value {
+    if action == "new" {
+        return match wallet.lock().unwrap().new_key() {
+            Ok(id) => json!({ "new_key": id }),
+            Err(e) => json!({ "error": e.to_string() }),
+        };
+    }
+    if action == "keys" {
+        let ids: Vec<u64> = wallet
+            .lock()
+            .unwrap()
+            .keys
+            .iter()
+            .map(|key| key.id())
+            .collect();
+        return json!({ "keys": ids });
+    }
+    if action != "balance" && action != "utxos" {
+        return invalid_command();
+    }
+    let confirmations = match val.get("confirmations") {
+        Some(confirmations) => match confirmations.as_u64() {
+            Some(confirmations) => confirmations,
+            None => return invalid_command(),
+        },
+        None => MIN_CONFIRMATIONS,
+    };
+    // the network is locked first, as when it tells the wallet about new blocks
+    let network = node.network().lock().unwrap();
+    let mut wallet = wallet.lock().unwrap();
+    if let Err(e) = wallet.sync(&network) {
+        return json!({ "error": e.to_string() });
+    }
+    if action == "balance" {
+        let balance = wallet.balance(&network.params, confirmations);
+        json!({
+            "balance": {
+                "confirmed": balance.confirmed,
+                "unconfirmed": balance.unconfirmed,
+                "height": network.state.height,
+            }
+        })
+    } else {
+        let page = wallet.utxos.page(&UtxoFilter::default(), None, usize::MAX);
+        let outputs: Vec<Value> = page
+            .utxos
+            .into_iter()
+            .map(|(output, entry)| {
+                json!({
+                    "id": output.id,
+                    "amount": output.amount,
+                    "height": entry.height,
+                    "coinbase": entry.coinbase,
+                })
+            })
+            .collect();
+        json!({ "utxos": outputs })
+    }
+}
+
+/// Runs a single JSON command and returns its JSON response.
+fn handle_commands(data: &str, node: &Node, wallet: &Mutex<Wallet>) -> Value {
+    let val: Value = match serde_json::from_str(data) {
+        Ok(val) => val,
+        Err(_) => return invalid_command(),
+    };
+    let result = if val.get("init").is_some() {
+        init_chain(data, &mut node.network().lock().unwrap())
     } else if let Some(field) = val.get("query") {
-        if field == "state" {
-            let _ = network.state();
-        } else if field == "heads" {
-            let _ = network.heads();
-        } else if field == "print" {
-            let _ = network.print_details();
-        } else {
-            println!("{{\"error\":\"invalid command\"}}");
-        }
-    } else if let Some(_) = val.get("block") {
-        let sbv = submit_block(data, network);
-        if sbv.is_ok() {
-            return sbv;
-        } else {
-            println!("{{\"error\":\"invalid command\"}}");
+        query(data, field, node)
+    } else if val.get("block").is_some() {
+        submit_block(data, node)
+    } else if val.get("generate").is_some() {
+        generate(data, node)
+    } else if let Some(action) = val.get("wallet") {
+        return wallet_command(action, &val, node, wallet);
+    } else {
+        return invalid_command();
+    };
+    result.unwrap_or_else(|e| json!({ "error": format!("invalid command: {}", e) }))
+}
+
+/// Starts the network from a JSON snapshot.
+fn load_snapshot(data: &str, network: &mut Network) -> std::result::Result<(), String> {
+    let snapshot: UtxoSnapshot = serde_json::from_str(data).map_err(|e| e.to_string())?;
+    network.load_snapshot(snapshot).map_err(|e| e.to_string())
+}
+
+/// Returns the JSON snapshot of the main chain as of the block given by its hash or height.
+fn export_snapshot(block: &str, network: &Network) -> std::result::Result<Value, ValidationError> {
+    let hash = match block.parse::<u64>() {
+        Ok(height) => network.block_at_height(height)?.header.hash,
+        Err(_) => block
+            .parse::<BlockHash>()
+            .map_err(|_| ValidationError::UnknownBlock)?,
+    };
+    Ok(json!(network.export_snapshot(&hash)?))
+}
+
+/// Back-validates the snapshot base in a new thread, by replaying the blocks of the block store
+/// at the given path from the genesis block.
+fn verify_history(
+    params: ChainParams,
+    path: PathBuf,
+    base: SnapshotBase,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut history = Network::with_params(params, Box::new(SystemClock));
+        let result = match history.open_store(&path) {
+            Ok(()) => history.verify_snapshot(&base).map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match result {
+            Ok(()) => eprintln!("snapshot {} verified against {}", base.hash, path.display()),
+            Err(e) => eprintln!(
+                "snapshot {} not verified against {}: {}",
+                base.hash,
+                path.display(),
+                e
+            ),
         }
+    })
+}
+
+/// Reads the whole file, or the standard input if the path is `-`.
+fn read_input(path: &str) -> io::Result<String> {
+    if path == "-" {
+        let mut data = String::new();
+        io::stdin().read_to_string(&mut data)?;
+        Ok(data)
     } else {
-        println!("{{\"error\":\"invalid command\"}}");
+        fs::read_to_string(path)
     }
+}
 
-    Ok(())
+/// Runs the commands in order, printing one response per command.
+///
+/// Returns `true` if none of the commands failed.
+fn run_commands<I: IntoIterator<Item = String>>(
+    commands: I,
+    node: &Node,
+    wallet: &Mutex<Wallet>,
+) -> bool {
+    let mut success = true;
+    for command in commands {
+        let res = handle_commands(&command, node, wallet);
+        success &= res.get("error").is_none();
+        println!("{}", res);
+    }
+    success
 }
 
-fn main() {
-    let mut network = Network::new(2);
+/// Turns a sequence of JSON values (bare blocks or commands) into commands with the given key.
+fn block_commands(data: &str, key: &str) -> Vec<String> {
+    serde_json::Deserializer::from_str(data)
+        .into_iter::<Value>()
+        .map(|val| match val {
+            Ok(val) if val.get(key).is_some() => val.to_string(),
+            Ok(val) => json!({ key: val }).to_string(),
+            Err(_) => String::new(),
+        })
+        .collect()
+}
 
+fn run_interactive(node: &Node, wallet: &Mutex<Wallet>) {
     // `()` can be used when no completer is required
     let mut rl = Editor::<()>::new();
     let _ = rl.load_history("history.txt");
@@ -84,7 +438,7 @@ fn main() {
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                let _ = handle_commands(line.as_str(), &mut network);
+                println!("{}", handle_commands(line.as_str(), node, wallet));
                 println!();
             }
             Err(ReadlineError::Interrupted) => {
@@ -103,3 +457,169 @@ fn main() {
     }
     rl.save_history("history.txt").unwrap();
 }
+
+fn main() {
+    let args = parse_args();
+    let params = match ChainParams::named(&args.network) {
+        Some(params) => params,
+        None => match ChainParams::load(&args.network) {
+            Ok(params) => params,
+            Err(e) => {
+                eprintln!("cannot load chain parameters {}: {}", args.network, e);
+                process::exit(2);
+            }
+        },
+    };
+    let datadir = args.datadir.map(|datadir| datadir.join(&params.name));
+    if let Some(datadir) = &datadir {
+        if let Err(e) = fs::create_dir_all(datadir) {
+            eprintln!("cannot create {}: {}", datadir.display(), e);
+            process::exit(2);
+        }
+    }
+    let mut network = Network::with_params(params.clone(), Box::new(SystemClock));
+    network.prune_depth = args.prune;
+    let snapshot_path = datadir
+        .as_ref()
+        .map(|datadir| datadir.join("snapshot.json"));
+    if let Some(snapshot_path) = snapshot_path.as_ref().filter(|path| path.exists()) {
+        let loaded = fs::read_to_string(snapshot_path)
+            .map_err(|e| e.to_string())
+            .and_then(|data| load_snapshot(&data, &mut network));
+        if let Err(e) = loaded {
+            eprintln!("cannot load {}: {}", snapshot_path.display(), e);
+            process::exit(2);
+        }
+    }
+    let mut bans = BanList::new();
+    let mut wallet = Wallet::new();
+    if let Some(datadir) = &datadir {
+        let store_path = datadir.join("blocks.dat");
+        if let Err(e) = network.open_store(&store_path) {
+            eprintln!("cannot load {}: {}", store_path.display(), e);
+            process::exit(2);
+        }
+        let ban_path = datadir.join("banlist.json");
+        bans = match BanList::load(&ban_path) {
+            Ok(bans) => bans,
+            Err(e) => {
+                eprintln!("cannot load {}: {}", ban_path.display(), e);
+                process::exit(2);
+            }
+        };
+        let wallet_path = datadir.join("wallet.json");
+        wallet = match Wallet::load(&wallet_path) {
+            Ok(wallet) => wallet,
+            Err(e) => {
+                eprintln!("cannot load {}: {}", wallet_path.display(), e);
+                process::exit(2);
+            }
+        };
+    }
+    let verification = match (args.verify_history, &network.snapshot) {
+        (Some(path), Some(base)) => Some(verify_history(params, path, base.clone())),
+        (Some(_), None) => {
+            eprintln!("the chain was not started from a snapshot, there is no history to verify");
+            None
+        }
+        (None, _) => None,
+    };
+    // synced now, the wallet then follows the main chain as blocks are accepted
+    let _ = wallet.sync(&network);
+    let wallet = Arc::new(Mutex::new(wallet));
+    network.listeners.push(Box::new(wallet.clone()));
+    let node = Node::with_bans(Arc::new(Mutex::new(network)), bans);
+    if let Some(addr) = &args.listen {
+        if let Err(e) = node.listen(addr) {
+            eprintln!("cannot listen on {}: {}", addr, e);
+            process::exit(2);
+        }
+    }
+    for addr in &args.connect {
+        if let Err(e) = node.connect(addr) {
+            eprintln!("cannot connect to {}: {}", addr, e);
+        }
+    }
+
+    let success = match args.mode {
+        Mode::Interactive => {
+            run_interactive(&node, &wallet);
+            true
+        }
+        Mode::Batch(path) => {
+            let commands: Vec<String> = if path == "-" {
+                io::stdin()
+                    .lock()
+                    .lines()
+                    .map_while(io::Result::ok)
+                    .collect()
+            } else {
+                match fs::read_to_string(&path) {
+                    Ok(data) => data.lines().map(String::from).collect(),
+                    Err(e) => {
+                        eprintln!("cannot read {}: {}", path, e);
+                        process::exit(2);
+                    }
+                }
+            };
+            let commands = commands.into_iter().filter(|c| !c.trim().is_empty());
+            run_commands(commands, &node, &wallet)
+        }
+        Mode::Init(ref path) | Mode::Submit(ref path) => {
+            let key = match args.mode {
+                Mode::Init(_) => "init",
+                _ => "block",
+            };
+            match read_input(path) {
+                Ok(data) => run_commands(block_commands(&data, key), &node, &wallet),
+                Err(e) => {
+                    eprintln!("cannot read {}: {}", path, e);
+                    process::exit(2);
+                }
+            }
+        }
+        Mode::Query(command) => run_commands(vec![command.to_string()], &node, &wallet),
+        Mode::Tree(format) => {
+            let network = node.network().lock().unwrap();
+            match format {
+                TreeFormat::Dot => print!("{}", network.tree_dot()),
+                TreeFormat::Json => println!("{:#}", network.tree_json()),
+            }
+            true
+        }
+        Mode::ExportSnapshot(block) => {
+            let network = node.network().lock().unwrap();
+            let res = export_snapshot(&block, &network)
+                .unwrap_or_else(|e| json!({ "error": e.to_string() }));
+            println!("{}", res);
+            res.get("error").is_none()
+        }
+        Mode::LoadSnapshot(path) => {
+            let Some(snapshot_path) = snapshot_path else {
+                eprintln!("snapshot load needs a data directory, see --datadir");
+                process::exit(2);
+            };
+            let data = match read_input(&path) {
+                Ok(data) => data,
+                Err(e) => {
+                    eprintln!("cannot read {}: {}", path, e);
+                    process::exit(2);
+                }
+            };
+            let loaded = load_snapshot(&data, &mut node.network().lock().unwrap())
+                .and_then(|()| fs::write(&snapshot_path, &data).map_err(|e| e.to_string()));
+            let res = match loaded {
+                Ok(()) => json!({ "ok": [] }),
+                Err(e) => json!({ "error": e }),
+            };
+            println!("{}", res);
+            res.get("error").is_none()
+        }
+    };
+    if let Some(verification) = verification {
+        let _ = verification.join();
+    }
+    if !success {
+        process::exit(1);
+    }
+}
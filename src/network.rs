@@ -1,7 +1,20 @@
-use super::{now, Block, Blockchain, Output};
+use super::{
+    Block, BlockHash, BlockHeader, BlockStore, Blockchain, ChainListener, ChainParams, ChainQuery,
+    Clock, Output, PowHasher, SnapshotBase, StoredBlock, SystemClock, Transaction,
+    TransactionProof, TreeFormat, UtxoDiff, UtxoEntry, UtxoFilter, UtxoProof, UtxoSet,
+    UtxoSnapshot, UtxoUndo, ValidationError, UTXO_PAGE_SIZE,
+};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::collections::{HashMap, HashSet, VecDeque};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Maximum number of blocks mined by a single call to
+/// [Network::generate](struct.Network.html#method.generate).
+pub const MAX_GENERATE: u64 = 10_000;
 
 /// A struct that represents a head (possible fork) in the network.
 #[allow(non_snake_case)]
@@ -9,7 +22,18 @@ use std::collections::{HashMap, HashSet, VecDeque};
 pub struct Head {
     pub height: u64,
     pub totalWork: u64,
-    pub hash: String,
+    pub hash: BlockHash,
+}
+
+impl Head {
+    /// Returns `true` if the head, received at `received_at`, wins over the other head, received
+    /// at `other_received_at`, under the [fork choice rule](struct.Network.html#method.get_main_chain).
+    pub fn wins_over(&self, received_at: u128, other: &Head, other_received_at: u128) -> bool {
+        if self.totalWork != other.totalWork {
+            return self.totalWork > other.totalWork;
+        }
+        received_at < other_received_at
+    }
 }
 
 /// A struct that represents the state of the network.
@@ -18,10 +42,93 @@ pub struct Head {
 pub struct ChainState {
     pub height: u64,
     pub totalWork: u64,
-    pub hash: String,
-    pub outputs: Vec<Output>,
+    pub hash: BlockHash,
+}
+
+/// A struct that represents a block known to the network, in the main chain or in a fork, with
+/// the data computed when it was accepted.
+#[derive(Clone, Debug)]
+pub struct IndexedBlock {
+    /// The header of the block, kept when its body is pruned.
+    pub header: BlockHeader,
+    /// The transactions of the block, empty if its body was [pruned](#structfield.pruned).
+    pub transactions: Vec<Transaction>,
+    /// The height of the block, the genesis block being at height 1.
+    pub height: u64,
+    /// The totalWork of the chain ending with the block.
+    pub total_work: u64,
+    /// The time the block was received at, from the [clock](trait.Clock.html) of the network.
+    pub received_at: u128,
+    /// The changes the block made to the UTXO set of its chain, unknown (empty) for the base
+    /// block of a [snapshot](struct.UtxoSnapshot.html) and once the block is pruned.
+    pub undo: UtxoUndo,
+    /// Whether the transactions of the block and its changes to the UTXO set were discarded, see
+    /// [Network::prune](struct.Network.html#method.prune).
+    pub pruned: bool,
+}
+
+impl IndexedBlock {
+    /// Returns a new indexed block, not pruned.
+    fn new(block: Block, height: u64, total_work: u64, received_at: u128, undo: UtxoUndo) -> Self {
+        IndexedBlock {
+            header: block.header(),
+            transactions: block.transactions,
+            height,
+            total_work,
+            received_at,
+            undo,
+            pruned: false,
+        }
+    }
+    /// Returns a copy of the block, unless its body was [pruned](#structfield.pruned).
+    pub fn block(&self) -> Option<Block> {
+        if self.pruned {
+            return None;
+        }
+        let header = &self.header;
+        Some(Block {
+            difficulty: header.difficulty,
+            hash: header.hash,
+            nonce: header.nonce,
+            predecessor: header.predecessor,
+            transactions: self.transactions.clone(),
+            utxo_root: header.utxo_root,
+        })
+    }
+}
+
+/// A struct that compares the chains of two heads since their common ancestor, to assess the
+/// impact of a reorganization from one to the other, see
+/// [Network::compare_heads](struct.Network.html#method.compare_heads).
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeadComparison {
+    /// The latest block both chains have in common.
+    pub ancestor: BlockHash,
+    /// The outputs unspent in the chain of the first head only, with their entries, in output
+    /// order.
+    pub only_first: Vec<(Output, UtxoEntry)>,
+    /// The outputs unspent in the chain of the second head only, with their entries, in output
+    /// order.
+    pub only_second: Vec<(Output, UtxoEntry)>,
+    /// The outputs spent by different transactions in the two chains, in output order.
+    pub double_spends: Vec<DoubleSpend>,
+}
+
+/// An output spent by different transactions in the chains of two heads.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DoubleSpend {
+    pub output: Output,
+    /// The entry of the output before it was spent.
+    pub entry: UtxoEntry,
+    /// The block and the transaction spending the output in the chain of the first head.
+    pub first: (BlockHash, Transaction),
+    /// The block and the transaction spending the output in the chain of the second head.
+    pub second: (BlockHash, Transaction),
 }
 
+/// A recent block in the main chain with its height, creation timestamp and totalWork.
+pub type RecentBlock = (u64, u128, u64);
+
 /// A struct that keeps track of the whole network.
 ///
 /// The `Network` stores the blocks in the main chain, possible forks, and the overall state.
@@ -29,27 +136,57 @@ pub struct ChainState {
 pub struct Network {
     /// Maximum number of blocks in [recent_blocks_queue](#structfield.recent_blocks_queue).
     pub recent_count_limit: usize,
-    /// It maps the block hash of the recent blocks in the main chain with their corresponding height, creation timestamp, totalWork, the block instance, outputs_set, and outputs.
+    /// It maps the block hash of the recent blocks in the main chain with their corresponding height, creation timestamp, and totalWork.
     ///
-    /// This acts as a cache which allows users to create forks from recent blocks quickly without
-    /// parsing the whole chain (or storing unspent outputs of older blocks of the chain).
-    pub recent_blocks: HashMap<String, (u64, u128, u64, Block, HashSet<Output>, Vec<Output>)>,
+    /// This acts as a cache which allows users to create forks from recent blocks quickly, by
+    /// undoing the changes of the following blocks, without parsing the whole chain.
+    pub recent_blocks: HashMap<BlockHash, RecentBlock>,
     /// A queue keeping track of the recent blocks.
-    pub recent_blocks_queue: VecDeque<String>,
-    /// It stores a copy of each of the possible forks by mapping the latest block hash of the fork with their corresponding height, creation timestamp, totalWork, and the chain instance.
-    pub forks: HashMap<String, (u64, u128, u64, Blockchain)>,
+    pub recent_blocks_queue: VecDeque<BlockHash>,
+    /// It stores the state of each of the possible forks by mapping the latest block hash of the fork with their corresponding height, creation timestamp, totalWork, and the chain instance.
+    ///
+    /// The chain instance only holds the unspent outputs and the height of the fork: its blocks
+    /// are read from the [block index](#structfield.block_index).
+    pub forks: HashMap<BlockHash, (u64, u128, u64, Blockchain)>,
     /// It stores the head of each of the possible forks.
     pub heads: HashSet<Head>,
-    /// A list of all the blocks and their creation timestamps in the main chain.
-    pub blocks: Vec<(Block, u128)>,
+    /// The hashes of all the blocks in the main chain, the blocks themselves being kept in the
+    /// [block index](#structfield.block_index).
+    pub blocks: Vec<BlockHash>,
     /// It stores the block hashes of all the blocks in the main chain.
-    pub blocks_set: HashSet<String>,
+    pub blocks_set: HashSet<BlockHash>,
+    /// It maps the hash of every block in the main chain and in the forks with its height,
+    /// totalWork, receipt time and changes to the UTXO set.
+    pub block_index: HashMap<BlockHash, IndexedBlock>,
     /// The current state of the network.
     pub state: ChainState,
+    /// The clock used to timestamp received blocks.
+    pub clock: Box<dyn Clock>,
+    /// The listeners told about every block connected to or disconnected from the main chain.
+    pub listeners: Vec<Box<dyn ChainListener>>,
+    /// The proof-of-work algorithm of the [chain parameters](#structfield.params).
+    pub pow: Arc<dyn PowHasher>,
+    /// The consensus rules of the chain.
+    pub params: ChainParams,
+    /// The log every accepted block is appended to, if any.
+    pub store: Option<BlockStore>,
+    /// The base block the network was started from, if it was loaded from a
+    /// [snapshot](#method.load_snapshot). The blocks before it are unknown.
+    pub snapshot: Option<SnapshotBase>,
+    /// The number of blocks below the main chain head whose bodies are kept in memory, never
+    /// fewer than the [max reorg depth](struct.ChainParams.html#structfield.max_reorg_depth) of
+    /// the chain. The bodies of older blocks and their changes to the UTXO set are
+    /// [pruned](#method.prune), only their headers and totalWork being kept. `None` keeps every
+    /// body.
+    pub prune_depth: Option<u64>,
+    /// The hashes of the blocks whose bodies were not pruned yet, by height, so that pruning
+    /// visits each block once.
+    pub unpruned: BTreeMap<u64, Vec<BlockHash>>,
 }
 
 impl Network {
-    /// Creates a new `Network` instance.
+    /// Creates a new `Network` instance following the [regtest](struct.ChainParams.html#method.regtest)
+    /// chain parameters, whose genesis block is the first one initialized.
     ///
     /// # Arguments
     ///
@@ -60,47 +197,189 @@ impl Network {
     /// Basic usage:
     ///
     /// ```
+    /// # use mycoinlib::Network;
     /// let network = Network::new(2);
     /// ```
     pub fn new(recent_count_limit: usize) -> Self {
-        Network {
-            recent_count_limit: recent_count_limit,
+        Network::with_clock(recent_count_limit, Box::new(SystemClock))
+    }
+    /// Creates a new `Network` instance following the regtest chain parameters, which reads the
+    /// time from the given clock.
+    ///
+    /// # Arguments
+    ///
+    /// * `recent_count_limit` - Maximum number of blocks allowed in the recent blocks in the network.
+    /// * `clock` - The clock used to timestamp received blocks.
+    ///
+    /// # Examples
+    ///
+    /// Two heads with equal `totalWork`: the one received first is the main chain.
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, ManualClock, Network};
+    /// let clock = ManualClock::new(0);
+    /// let mut network = Network::with_clock(2, Box::new(clock.clone()));
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// network.init(genesis.clone()).unwrap();
+    ///
+    /// let mut blocks = vec![];
+    /// for nonce in 1..=2 {
+    ///     let mut block = Block::new(0, BlockHash::ZERO, nonce, genesis.hash.to_owned(), vec![]);
+    ///     block.hash = block.hash();
+    ///     blocks.push(block);
+    /// }
+    /// clock.set(20);
+    /// network.submit(blocks[1].clone()).unwrap();
+    /// clock.set(10);
+    /// network.submit(blocks[0].clone()).unwrap();
+    /// assert_eq!(network.state.hash, blocks[0].hash);
+    /// ```
+    pub fn with_clock(recent_count_limit: usize, clock: Box<dyn Clock>) -> Self {
+        Network::with_params(
+            ChainParams {
+                recent_count_limit,
+                ..ChainParams::regtest()
+            },
+            clock,
+        )
+    }
+    /// Creates a new `Network` instance following the given [chain parameters](struct.ChainParams.html).
+    ///
+    /// If the parameters have a genesis block, the network is initialized with it.
+    ///
+    /// # Arguments
+    ///
+    /// * `params` - The consensus rules of the chain.
+    /// * `clock` - The clock used to timestamp received blocks.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{
+    /// #     Block, BlockHash, ChainParams, Hashable, Network, SystemClock, ValidationError,
+    /// # };
+    /// let params = ChainParams {
+    ///     pow: String::from("sha256d"),
+    ///     ..ChainParams::regtest()
+    /// };
+    /// let mut network = Network::with_params(params, Box::new(SystemClock));
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// assert_eq!(network.init(genesis.clone()), Err(ValidationError::InvalidHash));
+    ///
+    /// genesis.hash = genesis.hash_with(&*network.pow);
+    /// network.init(genesis).unwrap();
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the proof-of-work algorithm of the parameters is unknown.
+    pub fn with_params(params: ChainParams, clock: Box<dyn Clock>) -> Self {
+        let recent_count_limit = params.recent_count_limit;
+        let pow = params.hasher();
+        let genesis = params.genesis.clone();
+        let mut network = Network {
+            recent_count_limit,
             recent_blocks: HashMap::new(),
             recent_blocks_queue: VecDeque::new(),
             forks: HashMap::new(),
             heads: HashSet::new(),
             blocks: vec![],
             blocks_set: HashSet::new(),
+            block_index: HashMap::new(),
             state: ChainState {
                 height: 0,
                 totalWork: 0,
-                hash: String::from(""),
-                outputs: vec![],
+                hash: BlockHash::ZERO,
             },
+            clock,
+            listeners: vec![],
+            pow,
+            params,
+            store: None,
+            snapshot: None,
+            prune_depth: None,
+            unpruned: BTreeMap::new(),
+        };
+        if let Some(genesis) = genesis {
+            network.init(genesis).expect("invalid genesis block");
         }
+        network
     }
-    /// Returns the blocks and the current state of the main chain.
+    /// Replays the blocks of the [block store](struct.BlockStore.html) at the given path, and
+    /// appends every block accepted from now on to it.
+    ///
+    /// The blocks are read one at a time, so with [pruning](#structfield.prune_depth) enabled
+    /// beforehand, the bodies of old blocks are discarded as they are replayed.
+    ///
+    /// Returns an error if the store can't be read.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, Network};
+    /// let path = std::env::temp_dir().join("mycoin-store-example.dat");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    ///
+    /// let mut network = Network::new(2);
+    /// network.open_store(&path).unwrap();
+    /// network.init(genesis.clone()).unwrap();
+    ///
+    /// let mut restarted = Network::new(2);
+    /// restarted.open_store(&path).unwrap();
+    /// assert_eq!(restarted.genesis_hash(), Some(genesis.hash));
+    /// # std::fs::remove_file(path).unwrap();
+    /// ```
+    pub fn open_store<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let (store, blocks) = BlockStore::open(path)?;
+        self.store = None;
+        for stored in blocks {
+            let _ = match stored? {
+                StoredBlock::Genesis(block) => self.init(block),
+                StoredBlock::Block(block) => self.submit(block),
+            };
+        }
+        self.store = Some(store);
+        Ok(())
+    }
+    /// Appends an accepted block to the block store, if any.
+    fn persist(&mut self, block: &Block, genesis: bool) {
+        if let Some(store) = &mut self.store {
+            if let Err(e) = store.append(block, genesis) {
+                eprintln!("cannot write block store: {}", e);
+            }
+        }
+    }
+    /// Returns the current unix timestamp of the clock of the network.
+    pub fn now(&self) -> u128 {
+        self.clock.now()
+    }
+    /// Computes the blocks and the current state of the main chain from all the heads.
     ///
     /// The fork choice rule is:
-    /// * The longest chain is chosen.
-    /// * If any two chains have the same height, the one with highest `totalWork` is chosen.
-    /// * If the chains have equal [totalWork](struct.Head.html#structfield.totalWork), the chain head which was created earlier is chosen.
-    pub fn get_main_chain(&mut self) -> (Vec<(Block, u128)>, HashSet<String>, ChainState) {
-        if self.heads.len() == 0 {
+    /// * The chain with the highest [totalWork](struct.Head.html#structfield.totalWork) is chosen.
+    /// * If the chains have equal totalWork, the chain head which was created earlier is chosen.
+    ///
+    /// The network keeps its [blocks](#structfield.blocks) and [state](#structfield.state) up to
+    /// date as blocks are accepted, so this is only needed to check them.
+    pub fn get_main_chain(&mut self) -> (Vec<BlockHash>, HashSet<BlockHash>, ChainState) {
+        if self.heads.is_empty() {
             return (
                 vec![],
                 HashSet::new(),
                 ChainState {
                     height: 0,
                     totalWork: 0,
-                    hash: String::from(""),
-                    outputs: vec![],
+                    hash: BlockHash::ZERO,
                 },
             );
         }
 
         let mut max_total_work = 0;
-        let mut max_total_work_head_hash = String::from("");
+        let mut max_total_work_head_hash = BlockHash::ZERO;
         for h in &self.heads {
             if h.totalWork > max_total_work {
                 max_total_work = h.totalWork;
@@ -121,8 +400,8 @@ impl Network {
 
         if heads_with_largest_total_work.len() > 1 {
             // multiple heads with the same max totalWork
-            let mut oldest_timestamp = u128::max_value();
-            let mut selected_head_hash = String::from("");
+            let mut oldest_timestamp = u128::MAX;
+            let mut selected_head_hash = BlockHash::ZERO;
             for h in heads_with_largest_total_work {
                 let (_height, created_at, _total_work, _fork) =
                     self.forks.get(&h.hash.to_owned()).unwrap();
@@ -134,22 +413,28 @@ impl Network {
             max_total_work_head_hash = selected_head_hash;
         }
 
-        let (height, _created_at, total_work, main_chain) = self
+        let (height, _created_at, total_work, _main_chain) = self
             .forks
             .get(&max_total_work_head_hash.to_owned())
             .unwrap();
 
-        let blocks = &main_chain.blocks;
-        let blocks_set = main_chain.blocks_set.clone();
+        // walk back from the head through the block index
+        let mut blocks = vec![];
+        let mut next = self.block_index.get(&max_total_work_head_hash);
+        while let Some(indexed) = next {
+            blocks.push(indexed.header.hash);
+            next = self.block_index.get(&indexed.header.predecessor);
+        }
+        blocks.reverse();
+        let blocks_set = blocks.iter().copied().collect();
 
         (
-            blocks.clone(),
+            blocks,
             blocks_set,
             ChainState {
                 height: *height,
                 totalWork: *total_work,
                 hash: max_total_work_head_hash,
-                outputs: main_chain.outputs.clone(),
             },
         )
     }
@@ -161,59 +446,78 @@ impl Network {
     ///
     /// The main purpose of this method is to compute the unspent outputs at a
     /// particular block (which is usually an older block not present in [recent_blocks](#structfield.recent_blocks)).
-    pub fn compute_chain_at_block(&mut self, hash: String) -> (Blockchain, u64) {
-        let mut total_work = 0;
-        let mut blocks: Vec<(Block, u128)> = vec![];
-        let mut blocks_set = HashSet::new();
-        let mut utxos = HashSet::new();
-        let mut outputs: Vec<Output> = vec![];
-        let mut is_referred_block = false;
-        for block in &self.blocks {
-            if block.0.hash == hash {
-                is_referred_block = true;
-            }
-            let mut blocks_spent: HashSet<Output> = HashSet::new();
-            let mut blocks_created: HashSet<Output> = HashSet::new();
-            for transaction in &block.0.transactions {
-                blocks_spent.extend(transaction.inputs());
-                blocks_created.extend(transaction.outputs());
-            }
-
-            blocks.push(block.clone());
-            blocks_set.insert(block.0.hash.to_owned());
-            utxos.retain(|output| !blocks_spent.contains(output));
-            utxos.extend(blocks_created);
-            outputs = utxos.clone().into_iter().collect();
-            total_work += u64::pow(16, block.0.difficulty);
-            if is_referred_block {
-                break;
-            }
-        }
+    /// The block may be in the main chain or in any of the forks. The unspent outputs are
+    /// computed by [utxos_at](#method.utxos_at) instead of replaying the chain.
+    pub fn compute_chain_at_block(&mut self, hash: BlockHash) -> (Blockchain, u64) {
+        let (Some(indexed), Ok(utxos)) = (self.block_index.get(&hash), self.utxos_at(&hash)) else {
+            return (Blockchain::new(), 0);
+        };
         (
             Blockchain {
-                blocks: blocks,
-                blocks_set: blocks_set,
-                outputs: outputs,
-                outputs_set: utxos,
+                utxos,
+                height: indexed.height,
             },
-            total_work,
+            indexed.total_work,
         )
     }
     /// Creates a new genesis block.
     ///
-    /// Returns `true` if the block was added successful, otherwise returns `false`.
-    pub fn init(&mut self, block: Block) -> bool {
+    /// The network is bound to a single genesis block: the one of its
+    /// [chain parameters](struct.ChainParams.html#structfield.genesis) if any, otherwise the
+    /// first one initialized. A genesis block must have a zero predecessor and its transactions
+    /// can't have inputs.
+    ///
+    /// Returns an error if the block was not added, e.g. because it was not mined with the
+    /// [proof-of-work algorithm](#structfield.pow) of the network or is not its genesis block.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, Network, ValidationError};
+    /// let mut network = Network::new(2);
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// network.init(genesis.clone()).unwrap();
+    /// assert_eq!(network.init(genesis.clone()), Err(ValidationError::DuplicateHash));
+    ///
+    /// let mut other = Block::new(0, BlockHash::ZERO, 1, BlockHash::ZERO, vec![]);
+    /// other.hash = other.hash();
+    /// assert_eq!(network.init(other), Err(ValidationError::InvalidGenesis));
+    ///
+    /// let mut orphan = Block::new(0, BlockHash::ZERO, 0, genesis.hash, vec![]);
+    /// orphan.hash = orphan.hash();
+    /// assert_eq!(
+    ///     Network::new(2).init(orphan),
+    ///     Err(ValidationError::GenesisPredecessor)
+    /// );
+    /// ```
+    pub fn init(&mut self, block: Block) -> Result<(), ValidationError> {
+        if let Some(genesis) = self.genesis_hash() {
+            if genesis == block.hash {
+                return Err(ValidationError::DuplicateHash);
+            }
+            return Err(ValidationError::InvalidGenesis);
+        }
+        if let Some(genesis) = &self.params.genesis {
+            if genesis.hash != block.hash {
+                return Err(ValidationError::InvalidGenesis);
+            }
+        }
+        block.check_genesis()?;
+        self.check_block(&block)?;
         let mut blockchain = Blockchain::new();
         let bhash = block.hash.to_owned();
-        if self.forks.contains_key(&bhash) || self.blocks_set.contains(&bhash) {
-            println!("{{\"error\":\"duplicate hash\"}}");
-            return false;
-        }
         let total_work = u64::pow(16, block.difficulty);
-        let timestamp = now();
-        if !blockchain.init(block.clone(), timestamp) {
-            return false;
-        }
+        let timestamp = self.clock.now();
+        blockchain.init(&block);
+        blockchain.check_utxo_root(&block, &self.params)?;
+        self.persist(&block, true);
+        let undo = UtxoSet::new().apply(&block, 1);
+        self.block_index.insert(
+            bhash,
+            IndexedBlock::new(block, 1, total_work, timestamp, undo),
+        );
+        self.unpruned.insert(1, vec![bhash]);
         self.forks.insert(
             bhash.to_owned(),
             (1, timestamp, total_work, blockchain.clone()),
@@ -223,209 +527,1176 @@ impl Network {
             totalWork: total_work,
             hash: bhash.to_owned(),
         });
-        let (main_chain_blocks, main_chain_blocks_set, main_chain_state) = self.get_main_chain();
-        self.blocks = main_chain_blocks;
-        self.blocks_set = main_chain_blocks_set;
-        self.state = main_chain_state;
-        // If the block was added to the main chain
-        if self.blocks.last().unwrap().0.hash.to_owned() == bhash.to_owned() {
-            self.recent_blocks.insert(
-                bhash.to_owned(),
-                (
-                    1,
-                    timestamp,
-                    total_work,
-                    block,
-                    blockchain.outputs_set.clone(),
-                    blockchain.outputs.clone(),
-                ),
-            );
-            if self.recent_blocks_queue.len() == self.recent_count_limit {
-                if let Some(v) = self.recent_blocks_queue.pop_front() {
-                    self.recent_blocks.remove(&v.to_owned());
+        self.blocks = vec![bhash];
+        self.blocks_set = HashSet::from([bhash]);
+        self.state = ChainState {
+            height: 1,
+            totalWork: total_work,
+            hash: bhash,
+        };
+        self.remember_recent(bhash, (1, timestamp, total_work));
+        self.notify(&bhash, true);
+
+        Ok(())
+    }
+    /// Returns the UTXO set of the main chain as of the given block, if it is one of the
+    /// [recent blocks](#structfield.recent_blocks) and so are all the blocks following it.
+    fn recent_utxos(&self, hash: &BlockHash) -> Option<UtxoSet> {
+        if !self.recent_blocks.contains_key(hash) || !self.blocks_set.contains(hash) {
+            return None;
+        }
+        let following = self.main_position(self.block_index[hash].height) + 1;
+        if self.blocks[following..]
+            .iter()
+            .any(|hash| !self.recent_blocks.contains_key(hash) || self.block_index[hash].pruned)
+        {
+            return None;
+        }
+        let mut utxos = self.utxos()?.clone();
+        for hash in self.blocks[following..].iter().rev() {
+            utxos.undo(&self.block_index[hash].undo);
+        }
+        Some(utxos)
+    }
+    /// Returns the unspent outputs of the main chain, if the network was initialized.
+    pub fn utxos(&self) -> Option<&UtxoSet> {
+        self.head_utxos(&self.state.hash)
+    }
+    /// Returns the unspent outputs of the chain ending with the given head, if any.
+    pub fn head_utxos(&self, hash: &BlockHash) -> Option<&UtxoSet> {
+        self.forks.get(hash).map(|(_, _, _, chain)| &chain.utxos)
+    }
+    /// Returns the net changes from the unspent outputs as of the block `from` to those as of
+    /// the block `to`. The blocks may be on any branches: the blocks from `from` back to the
+    /// common ancestor are undone and those from the ancestor to `to` are redone, using the
+    /// changes each block made when it was accepted.
+    ///
+    /// Returns an error if a block is unknown, or if a block to undo or redo was
+    /// [pruned](#method.prune).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, Network, Output};
+    /// let mut network = Network::new(2);
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// network.init(genesis.clone()).unwrap();
+    ///
+    /// let base = network.generate(1, 100, None).unwrap()[0];
+    /// let main = network.generate(2, 200, None).unwrap();
+    /// let fork = network.generate(1, 300, Some(&base)).unwrap();
+    ///
+    /// let diff = network.utxo_diff(&main[1], &fork[0]).unwrap();
+    /// let ids = |utxos: Vec<(&Output, _)>| utxos.iter().map(|(o, _)| o.id).collect::<Vec<_>>();
+    /// assert_eq!(ids(diff.spent()), vec![200, 201]);
+    /// assert_eq!(ids(diff.created()), vec![300]);
+    ///
+    /// let utxos = network.utxos_at(&base).unwrap();
+    /// assert_eq!(utxos.outputs().map(|o| o.id).collect::<Vec<_>>(), vec![100]);
+    /// assert_eq!(&network.utxos_at(&fork[0]).unwrap(), network.head_utxos(&fork[0]).unwrap());
+    /// ```
+    pub fn utxo_diff(&self, from: &BlockHash, to: &BlockHash) -> Result<UtxoDiff, ValidationError> {
+        let ancestor = self.common_ancestor(from, to)?;
+        let mut diff = UtxoDiff::default();
+        let mut indexed = self.indexed_block(from)?;
+        while indexed.header.hash != ancestor {
+            if indexed.pruned {
+                return Err(ValidationError::BlockPruned);
+            }
+            diff.undo(&indexed.undo);
+            indexed = self.indexed_block(&indexed.header.predecessor)?;
+        }
+        for hash in self.blocks_between(&ancestor, to)?.iter().skip(1) {
+            let indexed = &self.block_index[hash];
+            if indexed.pruned {
+                return Err(ValidationError::BlockPruned);
+            }
+            diff.redo(&indexed.undo);
+        }
+        Ok(diff)
+    }
+    /// Returns the unspent outputs as of any known block, on the main chain or on a fork.
+    ///
+    /// The outputs of a head are copied, and those of any other block are computed from the
+    /// main chain head with a [diff](#method.utxo_diff) instead of replaying the whole chain.
+    ///
+    /// Returns an error if the block is unknown, or if it is not a head and a block between it
+    /// and the main chain head was [pruned](#method.prune).
+    pub fn utxos_at(&self, hash: &BlockHash) -> Result<UtxoSet, ValidationError> {
+        if let Some(utxos) = self.head_utxos(hash) {
+            return Ok(utxos.clone());
+        }
+        let diff = self.utxo_diff(&self.state.hash, hash)?;
+        let mut utxos = self.utxos().ok_or(ValidationError::NotInitialized)?.clone();
+        utxos.apply_diff(&diff);
+        Ok(utxos)
+    }
+    /// Returns a [proof](struct.UtxoProof.html) that the output is, or is not, unspent as of the
+    /// given block, to verify against the [UTXO root](struct.Block.html#structfield.utxo_root)
+    /// of the block on chains [committing](struct.ChainParams.html#structfield.utxo_commitment)
+    /// to it.
+    ///
+    /// Returns an error if the block is unknown.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{
+    /// #     Block, BlockHash, ChainParams, Hashable, Network, Output, SystemClock, UtxoSet,
+    /// #     ValidationError,
+    /// # };
+    /// let params = ChainParams {
+    ///     utxo_commitment: true,
+    ///     ..ChainParams::regtest()
+    /// };
+    /// let mut network = Network::with_params(params, Box::new(SystemClock));
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// assert_eq!(network.init(genesis.clone()), Err(ValidationError::InvalidUtxoRoot));
+    ///
+    /// genesis.utxo_root = Some(UtxoSet::new().root());
+    /// genesis.hash = genesis.hash();
+    /// network.init(genesis).unwrap();
+    /// let hash = network.generate(2, 100, None).unwrap()[1];
+    /// let root = network.get_block(&hash).unwrap().utxo_root.unwrap();
+    ///
+    /// let coin = network.utxos().unwrap().outputs().next().copied().unwrap();
+    /// let proof = network.prove_utxo(&hash, &coin).unwrap();
+    /// assert!(proof.verify(&coin, &root).unwrap().is_some());
+    ///
+    /// let spent = Output { id: 99, amount: 1 };
+    /// let proof = network.prove_utxo(&hash, &spent).unwrap();
+    /// assert_eq!(proof.verify(&spent, &root), Ok(None));
+    /// ```
+    pub fn prove_utxo(
+        &self,
+        hash: &BlockHash,
+        output: &Output,
+    ) -> Result<UtxoProof, ValidationError> {
+        Ok(self.utxos_at(hash)?.prove(output))
+    }
+    /// Returns a [proof](struct.TransactionProof.html) that the transaction is in the given
+    /// block, to verify against the header of the block, e.g. by a
+    /// [light client](struct.LightClient.html).
+    ///
+    /// Returns an error if the block is unknown, its body was [pruned](#method.prune) or the
+    /// transaction is not in it.
+    pub fn prove_transaction(
+        &self,
+        hash: &BlockHash,
+        transaction: &Transaction,
+    ) -> Result<TransactionProof, ValidationError> {
+        let indexed = self.indexed_block(hash)?;
+        let block = indexed.block().ok_or(ValidationError::BlockPruned)?;
+        block
+            .transactions
+            .iter()
+            .position(|t| t == transaction)
+            .and_then(|index| TransactionProof::new(&block, index))
+            .ok_or(ValidationError::UnknownTransaction)
+    }
+    /// Returns the outputs spent by the blocks after the ancestor up to the head, each with its
+    /// entry and the block and transaction spending it.
+    fn branch_spends(
+        &self,
+        ancestor: &BlockHash,
+        head: &BlockHash,
+    ) -> Result<HashMap<Output, (UtxoEntry, BlockHash, &Transaction)>, ValidationError> {
+        let mut spends = HashMap::new();
+        for hash in self.blocks_between(ancestor, head)?.iter().skip(1) {
+            let indexed = &self.block_index[hash];
+            if indexed.pruned {
+                return Err(ValidationError::BlockPruned);
+            }
+            let entries: HashMap<&Output, &UtxoEntry> =
+                indexed.undo.spent.iter().map(|(o, e)| (o, e)).collect();
+            for transaction in &indexed.transactions {
+                for input in &transaction.inputs {
+                    if let Some(entry) = entries.get(input) {
+                        spends.insert(*input, (**entry, *hash, transaction));
+                    }
                 }
             }
-            self.recent_blocks_queue.push_back(bhash.to_owned());
         }
-        println!("{{\"ok\":[]}}");
-
-        true
+        Ok(spends)
+    }
+    /// Compares the chains of two heads since their common ancestor: the outputs unspent on one
+    /// side only, and the outputs spent by different transactions on each side.
+    ///
+    /// Returns an error if a block is not one of the [heads](#structfield.heads), or if the body
+    /// of a block after the common ancestor was [pruned](#method.prune).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, Network, Output, Transaction};
+    /// let coin = Output { id: 1, amount: 50 };
+    /// let block = |predecessor, inputs, id| {
+    ///     let outputs = vec![Output { id, amount: 50 }];
+    ///     let transaction = Transaction { inputs, outputs };
+    ///     let mut block = Block::new(0, BlockHash::ZERO, 0, predecessor, vec![transaction]);
+    ///     block.hash = block.hash();
+    ///     block
+    /// };
+    /// let mut network = Network::new(2);
+    /// let genesis = block(BlockHash::ZERO, vec![], 1);
+    /// network.init(genesis.clone()).unwrap();
+    ///
+    /// let first = block(genesis.hash, vec![coin], 2);
+    /// let second = block(genesis.hash, vec![coin], 3);
+    /// network.submit(first.clone()).unwrap();
+    /// network.submit(second.clone()).unwrap();
+    ///
+    /// let comparison = network.compare_heads(&first.hash, &second.hash).unwrap();
+    /// assert_eq!(comparison.ancestor, genesis.hash);
+    /// assert_eq!(comparison.only_first[0].0.id, 2);
+    /// assert_eq!(comparison.only_second[0].0.id, 3);
+    /// let double_spend = &comparison.double_spends[0];
+    /// assert_eq!(double_spend.output, coin);
+    /// assert_eq!(double_spend.first, (first.hash, first.transactions[0].clone()));
+    /// assert_eq!(double_spend.second, (second.hash, second.transactions[0].clone()));
+    /// ```
+    pub fn compare_heads(
+        &self,
+        first: &BlockHash,
+        second: &BlockHash,
+    ) -> Result<HeadComparison, ValidationError> {
+        if self.head_utxos(first).is_none() || self.head_utxos(second).is_none() {
+            return Err(ValidationError::NotAHead);
+        }
+        let ancestor = self.common_ancestor(first, second)?;
+        let diff = self.utxo_diff(first, second)?;
+        let first_spends = self.branch_spends(&ancestor, first)?;
+        let second_spends = self.branch_spends(&ancestor, second)?;
+        let mut double_spends: Vec<DoubleSpend> = first_spends
+            .iter()
+            .filter_map(|(output, (entry, block, transaction))| {
+                let (_, other_block, other_transaction) = second_spends.get(output)?;
+                if transaction == other_transaction {
+                    return None;
+                }
+                Some(DoubleSpend {
+                    output: *output,
+                    entry: *entry,
+                    first: (*block, (*transaction).clone()),
+                    second: (*other_block, (*other_transaction).clone()),
+                })
+            })
+            .collect();
+        double_spends.sort_unstable_by_key(|double_spend| double_spend.output);
+        let owned = |utxos: Vec<(&Output, &UtxoEntry)>| -> Vec<(Output, UtxoEntry)> {
+            utxos.into_iter().map(|(o, e)| (*o, *e)).collect()
+        };
+        Ok(HeadComparison {
+            ancestor,
+            only_first: owned(diff.spent()),
+            only_second: owned(diff.created()),
+            double_spends,
+        })
+    }
+    /// Returns a [snapshot](struct.UtxoSnapshot.html) of the unspent outputs of the main chain as
+    /// of the given block.
+    ///
+    /// Returns an error if the block is unknown, not in the main chain or its body was
+    /// [pruned](#method.prune).
+    pub fn export_snapshot(&self, hash: &BlockHash) -> Result<UtxoSnapshot, ValidationError> {
+        let genesis = self.genesis_hash().ok_or(ValidationError::NotInitialized)?;
+        let indexed = self.indexed_block(hash)?;
+        if !self.blocks_set.contains(hash) {
+            return Err(ValidationError::NotInMainChain);
+        }
+        let block = indexed.block().ok_or(ValidationError::BlockPruned)?;
+        Ok(UtxoSnapshot::new(
+            genesis,
+            block,
+            indexed.height,
+            indexed.total_work,
+            &self.utxos_at(hash)?,
+        ))
+    }
+    /// Starts the network from a [snapshot](struct.UtxoSnapshot.html): its base block becomes the
+    /// only known block, trusted along with the unspent outputs as of it, and only the blocks
+    /// following it are validated. Blocks before the base block are unknown, so forks can't
+    /// start below it.
+    ///
+    /// The network must not have any block besides its genesis block, which must be the one
+    /// of the snapshot. The snapshot is not written to the [block store](#structfield.store),
+    /// so it must be loaded again before the store is opened.
+    ///
+    /// Returns an error if the snapshot fails its [check](struct.UtxoSnapshot.html#method.check)
+    /// or if its base block was not mined with the [proof-of-work algorithm](#structfield.pow) of
+    /// the network.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, Network};
+    /// let mut network = Network::new(2);
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// network.init(genesis.clone()).unwrap();
+    /// let base = network.generate(3, 100, None).unwrap()[1];
+    /// let snapshot = network.export_snapshot(&base).unwrap();
+    ///
+    /// let mut node = Network::new(2);
+    /// node.load_snapshot(snapshot.clone()).unwrap();
+    /// assert_eq!(node.state.height, 3);
+    /// assert_eq!(node.genesis_hash(), Some(genesis.hash));
+    /// node.submit(network.get_block(&network.state.hash).unwrap()).unwrap();
+    /// assert_eq!(node.state.hash, network.state.hash);
+    /// assert_eq!(node.utxos(), network.utxos());
+    ///
+    /// assert_eq!(network.verify_snapshot(&snapshot.base), Ok(()));
+    /// ```
+    pub fn load_snapshot(&mut self, snapshot: UtxoSnapshot) -> Result<(), ValidationError> {
+        snapshot.check()?;
+        self.check_block(&snapshot.block)?;
+        let genesis = self
+            .params
+            .genesis
+            .as_ref()
+            .map(|block| block.hash)
+            .or_else(|| self.genesis_hash());
+        if genesis.is_some_and(|genesis| genesis != snapshot.base.genesis) {
+            return Err(ValidationError::InvalidGenesis);
+        }
+        if self.snapshot.is_some() || self.block_index.len() > 1 {
+            return Err(ValidationError::ChainNotEmpty);
+        }
+        let SnapshotBase {
+            hash,
+            height,
+            total_work,
+            ..
+        } = snapshot.base;
+        let timestamp = self.clock.now();
+        let utxos = snapshot.utxo_set();
+        let block = snapshot.block;
+        self.block_index = HashMap::from([(
+            hash,
+            IndexedBlock::new(block, height, total_work, timestamp, UtxoUndo::default()),
+        )]);
+        self.unpruned = BTreeMap::from([(height, vec![hash])]);
+        let chain = Blockchain { utxos, height };
+        self.forks = HashMap::from([(hash, (height, timestamp, total_work, chain))]);
+        self.heads = HashSet::from([Head {
+            height,
+            totalWork: total_work,
+            hash,
+        }]);
+        self.blocks = vec![hash];
+        self.blocks_set = HashSet::from([hash]);
+        self.state = ChainState {
+            height,
+            totalWork: total_work,
+            hash,
+        };
+        self.recent_blocks.clear();
+        self.recent_blocks_queue.clear();
+        self.remember_recent(hash, (height, timestamp, total_work));
+        self.snapshot = Some(snapshot.base);
+        Ok(())
+    }
+    /// Checks the base of a [snapshot](struct.UtxoSnapshot.html) against the history of this
+    /// network, e.g. one that replayed the blocks from the genesis block to back-validate the
+    /// snapshot a node was started from: the base block must be known with the same height and
+    /// totalWork, and the unspent outputs as of it must have the same content hash.
+    ///
+    /// Returns an error if they don't match, if this network was itself started from a
+    /// snapshot, or if the base block was [pruned](#method.prune).
+    pub fn verify_snapshot(&self, base: &SnapshotBase) -> Result<(), ValidationError> {
+        if self.snapshot.is_some() || self.genesis_hash() != Some(base.genesis) {
+            return Err(ValidationError::SnapshotMismatch);
+        }
+        let indexed = self
+            .indexed_block(&base.hash)
+            .map_err(|_| ValidationError::SnapshotMismatch)?;
+        let block = indexed.block().ok_or(ValidationError::BlockPruned)?;
+        let snapshot = UtxoSnapshot::new(
+            base.genesis,
+            block,
+            indexed.height,
+            indexed.total_work,
+            &self.utxos_at(&base.hash)?,
+        );
+        if snapshot.base != *base {
+            return Err(ValidationError::SnapshotMismatch);
+        }
+        Ok(())
+    }
+    /// Adds a new block of the main chain to the [recent blocks](#structfield.recent_blocks),
+    /// evicting the oldest one if there are too many.
+    fn remember_recent(&mut self, hash: BlockHash, recent: RecentBlock) {
+        self.recent_blocks.insert(hash, recent);
+        if self.recent_blocks_queue.len() == self.recent_count_limit {
+            if let Some(v) = self.recent_blocks_queue.pop_front() {
+                self.recent_blocks.remove(&v);
+            }
+        }
+        self.recent_blocks_queue.push_back(hash);
+    }
+    /// Returns `true` if a new head with the given creation time wins over the head of the main
+    /// chain under the [fork choice rule](#method.get_main_chain).
+    fn wins_over_main_head(&self, head: &Head, created_at: u128) -> bool {
+        let main_head = Head {
+            height: self.state.height,
+            totalWork: self.state.totalWork,
+            hash: self.state.hash,
+        };
+        match self.forks.get(&self.state.hash) {
+            Some((_, main_created_at, _, _)) => {
+                head.wins_over(created_at, &main_head, *main_created_at)
+            }
+            None => head.totalWork > main_head.totalWork,
+        }
+    }
+    /// Makes the known block with the given hash the head of the main chain: the blocks of the
+    /// main chain after the common ancestor are disconnected and those of the new branch are
+    /// connected, the [listeners](#structfield.listeners) being told about each of them.
+    fn reorganize(&mut self, hash: &BlockHash) -> Result<(), ValidationError> {
+        let ancestor = self.common_ancestor(&self.state.hash, hash)?;
+        let connected = self.blocks_between(&ancestor, hash)?;
+        let following = self.main_position(self.indexed_block(&ancestor)?.height) + 1;
+        let disconnected: Vec<BlockHash> = self.blocks.drain(following..).collect();
+        for hash in disconnected.iter().rev() {
+            self.blocks_set.remove(hash);
+            self.notify(hash, false);
+        }
+        for hash in connected.into_iter().skip(1) {
+            self.blocks.push(hash);
+            self.blocks_set.insert(hash);
+            self.notify(&hash, true);
+        }
+        Ok(())
+    }
+    /// Tells the [listeners](#structfield.listeners) that the block was connected to the main
+    /// chain, or disconnected from it.
+    fn notify(&mut self, hash: &BlockHash, connected: bool) {
+        let indexed = &self.block_index[hash];
+        for listener in &mut self.listeners {
+            match connected {
+                true => listener.connected(indexed),
+                false => listener.disconnected(indexed),
+            }
+        }
+    }
+    /// Checks the size limits, the proof of work and the difficulty of a block against the
+    /// [chain parameters](struct.ChainParams.html).
+    fn check_block(&self, block: &Block) -> Result<(), ValidationError> {
+        self.params.check_limits(block)?;
+        block.validate_with(&*self.pow)?;
+        if block.difficulty < self.params.min_difficulty {
+            return Err(ValidationError::DifficultyTooLow);
+        }
+        Ok(())
     }
     /// Submits a new block to the network.
     ///
-    /// Returns `true` if the block was added successful, otherwise returns `false`.
-    pub fn submit(&mut self, block: Block) -> bool {
-        if self.heads.len() == 0 {
-            println!("{{\"error\":\"must initialize first\"}}");
-            return false;
+    /// The main chain is kept up to date as blocks are submitted: a block extending it is
+    /// appended to it, and only a block making another head win over it switches to the
+    /// other branch.
+    ///
+    /// Returns an error if the block was not added.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, Network};
+    /// let mut network = Network::new(2);
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// network.init(genesis).unwrap();
+    ///
+    /// let main = network.generate(3, 100, None).unwrap();
+    /// let fork = network.generate(2, 200, Some(&main[0])).unwrap();
+    /// assert_eq!(network.state.hash, main[2]);
+    /// network.generate(1, 300, Some(&fork[1])).unwrap();
+    /// assert_eq!(network.blocks[3], fork[1]);
+    ///
+    /// let (blocks, blocks_set, state) = network.get_main_chain();
+    /// assert_eq!(blocks, network.blocks);
+    /// assert_eq!(blocks_set, network.blocks_set);
+    /// assert_eq!(state.hash, network.state.hash);
+    /// ```
+    pub fn submit(&mut self, block: Block) -> Result<(), ValidationError> {
+        if self.heads.is_empty() {
+            return Err(ValidationError::NotInitialized);
         }
 
-        let hash_copy = block.hash.to_owned();
-        let (hash_start, _) = hash_copy.split_at((2 + block.difficulty) as usize);
-        if hash_start != format!("0x{}", "0".repeat(block.difficulty as usize)) {
-            println!("{{\"error\":\"leading zeroes in block hash did not match difficulty\"}}");
-            return false;
-        }
+        self.check_block(&block)?;
 
         let bhash = block.hash.to_owned();
         let predecessor_hash = block.predecessor.to_owned();
-        if !self.forks.contains_key(&predecessor_hash)
-            && !self.blocks_set.contains(&predecessor_hash)
-        {
-            println!("{{\"error\":\"no predecessor found\"}}");
-            return false;
+        if !self.contains_block(&predecessor_hash) {
+            return Err(ValidationError::NoPredecessor);
         }
-        if self.forks.contains_key(&bhash) || self.blocks_set.contains(&bhash) {
-            println!("{{\"error\":\"duplicate hash\"}}");
-            return false;
+        if self.contains_block(&bhash) {
+            return Err(ValidationError::DuplicateHash);
+        }
+
+        if self.block_index[&predecessor_hash].header.difficulty > block.difficulty {
+            return Err(ValidationError::DifficultyDecreased);
         }
 
         let predecessor_height;
-        let mut _predecessor_created_at = 0 as u128;
         let predecessor_total_work;
+        let mut predecessor_created_at = None;
         let mut chain: Blockchain;
 
-        if self.forks.contains_key(&predecessor_hash) {
-            // predecessor is a head
-            let (
-                tmp_predecessor_height,
-                tmp_predecessor_created_at,
-                tmp_predecessor_total_work,
-                tmp_chain,
-            ) = self.forks.get(&predecessor_hash).unwrap();
-            predecessor_height = *tmp_predecessor_height;
-            _predecessor_created_at = *tmp_predecessor_created_at;
-            predecessor_total_work = *tmp_predecessor_total_work;
-            chain = tmp_chain.clone();
+        if let Some((height, created_at, total_work, head_chain)) =
+            self.forks.remove(&predecessor_hash)
+        {
+            // predecessor is a head, whose chain is extended without copying it
+            predecessor_height = height;
+            predecessor_created_at = Some(created_at);
+            predecessor_total_work = total_work;
+            chain = head_chain;
         } else {
-            // predecessor is not a head (but is a block in the main chain)
+            // predecessor is not a head (but is a block in the main chain or in a fork)
 
-            if self.recent_blocks.contains_key(&predecessor_hash) {
+            if let Some(utxos) = self.recent_utxos(&predecessor_hash) {
                 // predecessor is within the last `recent_count_limit` blocks
-                let (
-                    tmp_predecessor_height,
-                    tmp_predecessor_created_at,
-                    tmp_predecessor_total_work,
-                    _tmp_block,
-                    tmp_outputs_set,
-                    tmp_outputs,
-                ) = self.recent_blocks.get(&predecessor_hash).unwrap();
-                predecessor_height = *tmp_predecessor_height;
-                _predecessor_created_at = *tmp_predecessor_created_at;
-                predecessor_total_work = *tmp_predecessor_total_work;
-
-                let sb;
-                if self.blocks.len() >= predecessor_height as usize {
-                    sb = self.blocks[0..predecessor_height as usize].to_vec();
-                } else {
-                    sb = self.blocks.clone();
-                }
-                let mut blocks_to_exclude: HashSet<String> = HashSet::new();
-                for rb in &self.recent_blocks_queue {
-                    let (h, _, _, _, _, _) = self.recent_blocks.get(&rb.to_owned()).unwrap();
-                    if *h <= predecessor_height {
-                        blocks_to_exclude.insert(rb.to_owned());
-                    }
-                }
+                let (tmp_predecessor_height, _, tmp_predecessor_total_work) =
+                    self.recent_blocks[&predecessor_hash];
+                predecessor_height = tmp_predecessor_height;
+                predecessor_total_work = tmp_predecessor_total_work;
+
                 chain = Blockchain {
-                    blocks: sb,
-                    blocks_set: &self.blocks_set.clone() - &blocks_to_exclude.clone(),
-                    outputs: tmp_outputs.clone(),
-                    outputs_set: tmp_outputs_set.clone(),
+                    utxos,
+                    height: predecessor_height,
                 }
             } else {
-                // predecessor is older than the last `recent_count_limit` blocks
-                let (tmp_chain, tmp_predecessor_total_work) =
-                    self.compute_chain_at_block(predecessor_hash.to_owned());
-                predecessor_height = tmp_chain.blocks.len() as u64;
-                predecessor_total_work = tmp_predecessor_total_work;
-                chain = tmp_chain;
+                // predecessor is older than the last `recent_count_limit` blocks, or not in the
+                // main chain, and the blocks in between must not be pruned
+                let utxos = self.utxos_at(&predecessor_hash)?;
+                let predecessor = &self.block_index[&predecessor_hash];
+                predecessor_height = predecessor.height;
+                predecessor_total_work = predecessor.total_work;
+                chain = Blockchain {
+                    utxos,
+                    height: predecessor_height,
+                };
             }
         }
-        if chain.blocks[(predecessor_height - 1) as usize].0.difficulty > block.difficulty {
-            println!("{{\"error\":\"difficulty must not decrease\"}}");
-            return false;
-        }
 
-        let timestamp = now();
-        if !chain.submit(block.clone(), timestamp) {
-            return false;
-        }
-        self.forks.remove(&predecessor_hash);
-        self.forks.insert(
-            bhash.to_owned(),
-            (
-                predecessor_height + 1,
-                timestamp,
-                predecessor_total_work + u64::pow(16, block.difficulty),
-                chain.clone(),
-            ),
+        let timestamp = self.clock.now();
+        let undo = match chain.submit(&block, &self.params) {
+            Ok(undo) => undo,
+            Err(e) => {
+                if let Some(created_at) = predecessor_created_at {
+                    self.forks.insert(
+                        predecessor_hash,
+                        (
+                            predecessor_height,
+                            created_at,
+                            predecessor_total_work,
+                            chain,
+                        ),
+                    );
+                }
+                return Err(e);
+            }
+        };
+        let height = predecessor_height + 1;
+        let total_work = predecessor_total_work + u64::pow(16, block.difficulty);
+        self.persist(&block, false);
+        self.block_index.insert(
+            bhash,
+            IndexedBlock::new(block, height, total_work, timestamp, undo),
         );
+        self.unpruned.entry(height).or_default().push(bhash);
         self.heads.remove(&Head {
             height: predecessor_height,
             totalWork: predecessor_total_work,
             hash: predecessor_hash,
         });
-        self.heads.insert(Head {
-            height: predecessor_height + 1,
-            totalWork: predecessor_total_work + u64::pow(16, block.difficulty),
-            hash: bhash.to_owned(),
-        });
-        let (main_chain_blocks, main_chain_blocks_set, main_chain_state) = self.get_main_chain();
-        self.blocks = main_chain_blocks;
-        self.blocks_set = main_chain_blocks_set;
-        self.state = main_chain_state;
-        // If the block was added to the main chain
-        if self.blocks.last().unwrap().0.hash.to_owned() == bhash.to_owned() {
-            self.recent_blocks.insert(
-                bhash.to_owned(),
-                (
-                    predecessor_height + 1,
-                    timestamp,
-                    predecessor_total_work + u64::pow(16, block.difficulty),
-                    block,
-                    chain.outputs_set,
-                    chain.outputs.clone(),
-                ),
-            );
-            if self.recent_blocks_queue.len() == self.recent_count_limit {
-                if let Some(v) = self.recent_blocks_queue.pop_front() {
-                    self.recent_blocks.remove(&v.to_owned());
+        let head = Head {
+            height,
+            totalWork: total_work,
+            hash: bhash,
+        };
+        self.heads.insert(head.clone());
+
+        // Only a new head winning over the main chain changes it: extending the main chain
+        // connects the block alone, while a branch switch reorganizes the differing blocks.
+        if predecessor_hash == self.state.hash {
+            self.blocks.push(bhash);
+            self.blocks_set.insert(bhash);
+            self.notify(&bhash, true);
+        } else if self.wins_over_main_head(&head, timestamp) {
+            self.reorganize(&bhash)?;
+        }
+        if self.blocks_set.contains(&bhash) {
+            self.state = ChainState {
+                height,
+                totalWork: total_work,
+                hash: bhash,
+            };
+            self.remember_recent(bhash, (height, timestamp, total_work));
+        }
+        self.forks
+            .insert(bhash, (height, timestamp, total_work, chain));
+        self.prune();
+
+        Ok(())
+    }
+    /// Discards the bodies of the blocks more than [prune_depth](#structfield.prune_depth) blocks
+    /// below the main chain head, and their changes to the UTXO set, on the main chain and on the
+    /// forks, if pruning is enabled. New blocks can then only extend a fork from its head above
+    /// the pruned blocks.
+    ///
+    /// Blocks are pruned as they are accepted, so this is only needed after enabling pruning.
+    ///
+    /// Pruning only saves memory: the [block store](#structfield.store) keeps every block, which
+    /// is needed to rebuild the unspent outputs on restart, when the blocks are replayed and
+    /// pruned again.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, ChainParams, Hashable, Network, SystemClock};
+    /// let params = ChainParams {
+    ///     max_reorg_depth: 2,
+    ///     ..ChainParams::regtest()
+    /// };
+    /// let mut network = Network::with_params(params, Box::new(SystemClock));
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// network.init(genesis).unwrap();
+    /// let hashes = network.generate(4, 100, None).unwrap();
+    ///
+    /// network.prune_depth = Some(0);
+    /// network.prune();
+    /// assert!(network.get_block(&hashes[1]).is_none());
+    /// assert!(network.indexed_block(&hashes[1]).unwrap().pruned);
+    /// assert!(network.get_block(&hashes[2]).is_some());
+    /// assert_eq!(network.headers_after(&[hashes[0]], 1)[0].hash, hashes[1]);
+    /// ```
+    pub fn prune(&mut self) {
+        let Some(depth) = self.prune_depth else {
+            return;
+        };
+        let below = self
+            .state
+            .height
+            .saturating_sub(depth.max(self.params.max_reorg_depth));
+        // The blocks up to `below` are taken out of the unpruned ones, so they are visited once.
+        let kept = self.unpruned.split_off(&(below + 1));
+        let pruned = std::mem::replace(&mut self.unpruned, kept);
+        for hash in pruned.into_values().flatten() {
+            if let Some(indexed) = self.block_index.get_mut(&hash) {
+                indexed.transactions = vec![];
+                indexed.undo = UtxoUndo::default();
+                indexed.pruned = true;
+            }
+        }
+    }
+    /// Mines `count` blocks on top of the given block, or of the main chain head, and submits
+    /// them. Every block has the lowest difficulty allowed after its predecessor and a coinbase
+    /// paying the [block subsidy](struct.ChainParams.html#method.block_subsidy) to a new output:
+    /// `to` for the first block, `to + 1` for the second, and so on.
+    ///
+    /// Only available if the [chain parameters](struct.ChainParams.html#structfield.generate)
+    /// allow it, as on regtest. At most [MAX_GENERATE](constant.MAX_GENERATE.html) blocks are
+    /// mined at once, and the ids of their outputs must not overflow.
+    ///
+    /// Returns the hashes of the mined blocks, or an error along with the hashes of the blocks
+    /// accepted before it.
+    ///
+    /// # Examples
+    ///
+    /// A fork of two blocks from the genesis block, which becomes the main chain:
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, Network, ValidationError};
+    /// let mut network = Network::new(2);
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// network.init(genesis.clone()).unwrap();
+    ///
+    /// network.generate(1, 100, None).unwrap();
+    /// let fork = network.generate(2, 200, Some(&genesis.hash)).unwrap();
+    /// assert_eq!(network.state.hash, fork[1]);
+    /// assert_eq!(network.heads.len(), 2);
+    ///
+    /// let (mined, error) = network.generate(2, u64::MAX, None).unwrap_err();
+    /// assert_eq!((mined.len(), error), (0, ValidationError::InvalidGenerate));
+    /// ```
+    pub fn generate(
+        &mut self,
+        count: u64,
+        to: u64,
+        on: Option<&BlockHash>,
+    ) -> Result<Vec<BlockHash>, (Vec<BlockHash>, ValidationError)> {
+        if !self.params.generate {
+            return Err((vec![], ValidationError::GenerateDisabled));
+        }
+        if self.heads.is_empty() {
+            return Err((vec![], ValidationError::NotInitialized));
+        }
+        if count > MAX_GENERATE || to.checked_add(count.saturating_sub(1)).is_none() {
+            return Err((vec![], ValidationError::InvalidGenerate));
+        }
+        let mut predecessor = match on {
+            Some(hash) => *hash,
+            None => self.state.hash.to_owned(),
+        };
+        let mut hashes = vec![];
+        for i in 0..count {
+            match self.generate_on(&predecessor, to + i) {
+                Ok(hash) => predecessor = hash,
+                Err(e) => return Err((hashes, e)),
+            }
+            hashes.push(predecessor);
+        }
+        Ok(hashes)
+    }
+    /// Mines a block paying the block subsidy to the output `id` on top of the given block, and
+    /// submits it. Returns the hash of the block.
+    fn generate_on(
+        &mut self,
+        predecessor: &BlockHash,
+        id: u64,
+    ) -> Result<BlockHash, ValidationError> {
+        let predecessor = *predecessor;
+        let (difficulty, height) = match self.block_index.get(&predecessor) {
+            Some(indexed) => (indexed.header.difficulty, indexed.height),
+            None => return Err(ValidationError::NoPredecessor),
+        };
+        let coinbase = Transaction {
+            inputs: vec![],
+            outputs: vec![Output {
+                id,
+                amount: self.params.block_subsidy(height + 1),
+            }],
+        };
+        let mut block = Block::new(
+            difficulty.max(self.params.min_difficulty),
+            BlockHash::ZERO,
+            0,
+            predecessor,
+            vec![coinbase],
+        );
+        if self.params.utxo_commitment {
+            let mut utxos = self.utxos_at(&predecessor)?;
+            utxos.apply(&block, height + 1);
+            block.utxo_root = Some(utxos.root());
+        }
+        block.mine(&*self.pow)?;
+        while self.contains_block(&block.hash) {
+            block.nonce = block.nonce.wrapping_add(1);
+            block.mine(&*self.pow)?;
+        }
+        let hash = block.hash;
+        self.submit(block)?;
+        Ok(hash)
+    }
+    /// Returns the hash of the genesis block of the main chain, if the network was initialized.
+    pub fn genesis_hash(&self) -> Option<BlockHash> {
+        match &self.snapshot {
+            Some(base) => Some(base.genesis),
+            None => self.blocks.first().copied(),
+        }
+    }
+    /// Returns the height of the first known block of the main chain: 1 unless the network was
+    /// started from a [snapshot](#method.load_snapshot).
+    fn first_height(&self) -> u64 {
+        self.snapshot.as_ref().map_or(1, |base| base.height)
+    }
+    /// Returns the position in [blocks](#structfield.blocks) of the block of the main chain at
+    /// the given height, which must not be below the [first height](#method.first_height).
+    fn main_position(&self, height: u64) -> usize {
+        (height - self.first_height()) as usize
+    }
+    /// Returns `true` if the block is in the main chain or in any of the forks.
+    pub fn contains_block(&self, hash: &BlockHash) -> bool {
+        self.block_index.contains_key(hash)
+    }
+    /// Returns a copy of the block with the given hash from the main chain or any of the forks,
+    /// unless its body was [pruned](#method.prune).
+    pub fn get_block(&self, hash: &BlockHash) -> Option<Block> {
+        self.block_index.get(hash).and_then(IndexedBlock::block)
+    }
+    /// Returns the block with the given hash from the main chain or any of the forks, with its
+    /// height, totalWork and receipt time.
+    pub fn indexed_block(&self, hash: &BlockHash) -> Result<&IndexedBlock, ValidationError> {
+        self.block_index
+            .get(hash)
+            .ok_or(ValidationError::UnknownBlock)
+    }
+    /// Returns the block of the main chain at the given height, the genesis block being at
+    /// height 1.
+    pub fn block_at_height(&self, height: u64) -> Result<&IndexedBlock, ValidationError> {
+        let index = height
+            .checked_sub(self.first_height())
+            .ok_or(ValidationError::UnknownBlock)?;
+        match self.blocks.get(index as usize) {
+            Some(hash) => self.indexed_block(hash),
+            None => Err(ValidationError::UnknownBlock),
+        }
+    }
+    /// Returns the block with the given hash, or else the block of the main chain at the given
+    /// height.
+    fn block_by(
+        &self,
+        hash: &Option<BlockHash>,
+        height: &Option<u64>,
+    ) -> Result<&IndexedBlock, ValidationError> {
+        match (hash, height) {
+            (Some(hash), _) => self.indexed_block(hash),
+            (None, Some(height)) => self.block_at_height(*height),
+            (None, None) => Err(ValidationError::UnknownBlock),
+        }
+    }
+    /// Returns the ancestor of the indexed block at the given height, which must not be greater
+    /// than the height of the block.
+    fn ancestor_at<'a>(&'a self, mut indexed: &'a IndexedBlock, height: u64) -> &'a IndexedBlock {
+        if self.blocks_set.contains(&indexed.header.hash) {
+            if let Ok(ancestor) = self.block_at_height(height) {
+                return ancestor;
+            }
+        }
+        while indexed.height > height {
+            match self.block_index.get(&indexed.header.predecessor) {
+                Some(predecessor) => indexed = predecessor,
+                None => break,
+            }
+        }
+        indexed
+    }
+    /// Returns the hashes of up to `count` ancestors of a block, from its predecessor back to
+    /// the genesis block.
+    ///
+    /// Returns an error if the block is unknown.
+    pub fn ancestors(
+        &self,
+        hash: &BlockHash,
+        count: usize,
+    ) -> Result<Vec<BlockHash>, ValidationError> {
+        let mut indexed = self.indexed_block(hash)?;
+        let mut ancestors = vec![];
+        while ancestors.len() < count {
+            match self.block_index.get(&indexed.header.predecessor) {
+                Some(predecessor) => {
+                    ancestors.push(predecessor.header.hash);
+                    indexed = predecessor;
                 }
+                None => break,
             }
-            self.recent_blocks_queue.push_back(bhash.to_owned());
         }
-        println!("{{\"ok\":[]}}");
-
-        true
+        Ok(ancestors)
+    }
+    /// Returns the hash of the latest block that is an ancestor of, or equal to, both blocks,
+    /// e.g. the block two heads forked from.
+    ///
+    /// Returns an error if a block is unknown.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, Network};
+    /// let mut network = Network::new(2);
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// network.init(genesis.clone()).unwrap();
+    ///
+    /// let base = network.generate(1, 100, None).unwrap()[0];
+    /// let main = network.generate(3, 200, None).unwrap();
+    /// let fork = network.generate(1, 300, Some(&base)).unwrap();
+    /// assert_eq!(network.common_ancestor(&main[2], &fork[0]), Ok(base));
+    /// assert_eq!(network.ancestors(&fork[0], 5), Ok(vec![base, genesis.hash]));
+    /// assert_eq!(
+    ///     network.blocks_between(&base, &main[2]),
+    ///     Ok(vec![base, main[0], main[1], main[2]])
+    /// );
+    /// assert_eq!(network.block_at_height(3).unwrap().header.hash, main[0]);
+    /// ```
+    pub fn common_ancestor(
+        &self,
+        a: &BlockHash,
+        b: &BlockHash,
+    ) -> Result<BlockHash, ValidationError> {
+        let a = self.indexed_block(a)?;
+        let b = self.indexed_block(b)?;
+        let height = a.height.min(b.height);
+        let mut a = self.ancestor_at(a, height);
+        let mut b = self.ancestor_at(b, height);
+        while a.header.hash != b.header.hash {
+            a = self.indexed_block(&a.header.predecessor)?;
+            b = self.indexed_block(&b.header.predecessor)?;
+        }
+        Ok(a.header.hash)
+    }
+    /// Returns the hashes of the blocks from `from` to `to`, both included, in chain order.
+    ///
+    /// Returns an error if a block is unknown or if `from` is not an ancestor of `to`.
+    pub fn blocks_between(
+        &self,
+        from: &BlockHash,
+        to: &BlockHash,
+    ) -> Result<Vec<BlockHash>, ValidationError> {
+        let first = self.indexed_block(from)?;
+        let mut indexed = self.indexed_block(to)?;
+        if first.height > indexed.height {
+            return Err(ValidationError::NotAncestor);
+        }
+        let mut hashes = vec![indexed.header.hash];
+        while indexed.height > first.height {
+            indexed = self.indexed_block(&indexed.header.predecessor)?;
+            hashes.push(indexed.header.hash);
+        }
+        if indexed.header.hash != *from {
+            return Err(ValidationError::NotAncestor);
+        }
+        hashes.reverse();
+        Ok(hashes)
+    }
+    /// Returns a block locator of the main chain: the hashes of the 10 latest blocks followed by
+    /// hashes exponentially further apart, ending with the genesis block.
+    ///
+    /// A peer uses the locator to find the latest block of our main chain it also knows.
+    pub fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = vec![];
+        let mut step = 1;
+        let mut height = self.blocks.len();
+        while height > 0 {
+            locator.push(self.blocks[height - 1]);
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        if let Some(genesis) = self.genesis_hash() {
+            if locator.last() != Some(&genesis) {
+                locator.push(genesis);
+            }
+        }
+        locator
+    }
+    /// Returns up to `max` headers of the main chain following the first locator hash found in it.
+    ///
+    /// If none of the locator hashes is in the main chain, the headers following the genesis
+    /// block are returned.
+    pub fn headers_after(&self, locator: &[BlockHash], max: usize) -> Vec<BlockHeader> {
+        let start = locator
+            .iter()
+            .find_map(|hash| {
+                let indexed = self.block_index.get(hash)?;
+                if self.blocks_set.contains(hash) {
+                    Some(self.main_position(indexed.height))
+                } else {
+                    None
+                }
+            })
+            .unwrap_or(0);
+        self.blocks
+            .iter()
+            .skip(start + 1)
+            .take(max)
+            .map(|hash| self.block_index[hash].header.clone())
+            .collect()
+    }
+    /// Returns the JSON response to a [query about blocks](enum.ChainQuery.html):
+    /// * `block`: `{"block":{...}}`, the block with its `height`, `totalWork`, `receivedAt` and
+    ///   whether it is on the `mainChain`. Only the header of a [pruned](#method.prune) block is
+    ///   returned, with `"pruned": true`.
+    /// * `ancestors`: `{"ancestors":[hashes]}`, see [ancestors](#method.ancestors).
+    /// * `common_ancestor`: `{"common_ancestor":hash}`, see
+    ///   [common_ancestor](#method.common_ancestor).
+    /// * `range`: `{"range":[hashes]}`, see [blocks_between](#method.blocks_between).
+    /// * `utxos`: `{"utxos":[outputs],"count":n,"total":amount,"next":cursor}`, each output with
+    ///   the `height` of its block and whether it is a `coinbase`, see
+    ///   [UtxoSet::page](struct.UtxoSet.html#method.page).
+    /// * `state_at`: `{"state":{...}}`, shaped like the response of [state_json](#method.state_json)
+    ///   with the outputs in order, see [utxos_at](#method.utxos_at).
+    /// * `diff`: `{"diff":{"created":[outputs],"spent":[outputs]}}`, each output as in `utxos`,
+    ///   see [utxo_diff](#method.utxo_diff).
+    /// * `compare_heads`: `{"compare_heads":{"ancestor":hash,"onlyFirst":[outputs],
+    ///   "onlySecond":[outputs],"doubleSpends":[...]}}`, each double spend with its `output` and
+    ///   the two `spends`, by `block` and `transaction`, see [compare_heads](#method.compare_heads).
+    /// * `prove`: `{"prove":{"block":hash,"utxoRoot":root,"proof":{...}}}`, see
+    ///   [prove_utxo](#method.prove_utxo).
+    /// * `prove_transaction`: `{"prove_transaction":{"block":hash,"index":n,"count":n,"branch":[hash,...]}}`,
+    ///   for the transaction creating the output, see [prove_transaction](#method.prove_transaction).
+    /// * `tree`: `{"tree":...}`, see [tree_json](#method.tree_json) and [tree_dot](#method.tree_dot).
+    pub fn query_json(&self, query: &ChainQuery) -> Result<Value, ValidationError> {
+        if self.heads.is_empty() {
+            return Err(ValidationError::NotInitialized);
+        }
+        Ok(match query {
+            ChainQuery::Block { hash, height } => {
+                let indexed = self.block_by(hash, height)?;
+                let mut block = match indexed.block() {
+                    Some(block) => json!(block),
+                    None => json!(indexed.header),
+                };
+                if indexed.pruned {
+                    block["pruned"] = json!(true);
+                }
+                block["height"] = json!(indexed.height);
+                block["totalWork"] = json!(indexed.total_work);
+                block["receivedAt"] = json!(indexed.received_at);
+                block["mainChain"] = json!(self.blocks_set.contains(&indexed.header.hash));
+                json!({ "block": block })
+            }
+            ChainQuery::Ancestors { hash, count } => {
+                let ancestors = self.ancestors(hash, count.unwrap_or(usize::MAX))?;
+                json!({ "ancestors": ancestors })
+            }
+            ChainQuery::CommonAncestor { hashes: [a, b] } => {
+                json!({ "common_ancestor": self.common_ancestor(a, b)? })
+            }
+            ChainQuery::Range { from, to } => json!({ "range": self.blocks_between(from, to)? }),
+            ChainQuery::Utxos {
+                head,
+                filter,
+                after,
+                limit,
+            } => {
+                let utxos = self
+                    .head_utxos(head.as_ref().unwrap_or(&self.state.hash))
+                    .ok_or(ValidationError::NotAHead)?;
+                let page = utxos.page(
+                    filter,
+                    *after,
+                    limit.map_or(UTXO_PAGE_SIZE, NonZeroUsize::get),
+                );
+                let outputs: Vec<Value> = page.utxos.into_iter().map(utxo_json).collect();
+                json!({
+                    "utxos": outputs,
+                    "count": page.count,
+                    "total": page.total,
+                    "next": page.next,
+                })
+            }
+            ChainQuery::StateAt { hash, height } => {
+                let indexed = self.block_by(hash, height)?;
+                let utxos = self.utxos_at(&indexed.header.hash)?;
+                let page = utxos.page(&UtxoFilter::default(), None, usize::MAX);
+                let outputs: Vec<&Output> = page.utxos.into_iter().map(|(o, _)| o).collect();
+                json!({
+                    "state": {
+                        "height": indexed.height,
+                        "totalWork": indexed.total_work,
+                        "hash": indexed.header.hash,
+                        "outputs": outputs
+                    }
+                })
+            }
+            ChainQuery::Diff { from, to } => {
+                let diff = self.utxo_diff(from, to)?;
+                let created: Vec<Value> = diff.created().into_iter().map(utxo_json).collect();
+                let spent: Vec<Value> = diff.spent().into_iter().map(utxo_json).collect();
+                json!({ "diff": { "created": created, "spent": spent } })
+            }
+            ChainQuery::Prove { hash, output } => {
+                let indexed = self.indexed_block(hash.as_ref().unwrap_or(&self.state.hash))?;
+                let proof = self.prove_utxo(&indexed.header.hash, output)?;
+                json!({
+                    "prove": {
+                        "block": indexed.header.hash,
+                        "utxoRoot": indexed.header.utxo_root,
+                        "proof": proof,
+                    }
+                })
+            }
+            ChainQuery::ProveTransaction { hash, output } => {
+                let indexed = self.indexed_block(hash)?;
+                if indexed.pruned {
+                    return Err(ValidationError::BlockPruned);
+                }
+                let transaction = indexed
+                    .transactions
+                    .iter()
+                    .find(|t| t.outputs.contains(output))
+                    .ok_or(ValidationError::UnknownTransaction)?;
+                json!({ "prove_transaction": self.prove_transaction(hash, transaction)? })
+            }
+            ChainQuery::CompareHeads { heads: [a, b] } => {
+                let comparison = self.compare_heads(a, b)?;
+                let outputs = |utxos: &[(Output, UtxoEntry)]| -> Vec<Value> {
+                    utxos.iter().map(|(o, e)| utxo_json((o, e))).collect()
+                };
+                let double_spends: Vec<Value> = comparison
+                    .double_spends
+                    .iter()
+                    .map(|double_spend| {
+                        let (first_block, first_transaction) = &double_spend.first;
+                        let (second_block, second_transaction) = &double_spend.second;
+                        json!({
+                            "output": utxo_json((&double_spend.output, &double_spend.entry)),
+                            "spends": [
+                                { "block": first_block, "transaction": first_transaction },
+                                { "block": second_block, "transaction": second_transaction },
+                            ],
+                        })
+                    })
+                    .collect();
+                json!({
+                    "compare_heads": {
+                        "ancestor": comparison.ancestor,
+                        "onlyFirst": outputs(&comparison.only_first),
+                        "onlySecond": outputs(&comparison.only_second),
+                        "doubleSpends": double_spends,
+                    }
+                })
+            }
+            ChainQuery::Tree {
+                format: Some(TreeFormat::Dot),
+            } => json!({ "tree": self.tree_dot() }),
+            ChainQuery::Tree { .. } => json!({ "tree": self.tree_json() }),
+        })
     }
     /// Prints the current state of the network.
     ///
     /// Returns `true` if there is a longest chain, otherwise returns `false`.
     pub fn state(&mut self) -> bool {
-        if self.heads.len() == 0 {
-            println!("{{\"error\":\"must initialize first\"}}");
-            return false;
-        } else {
-            let j = json!({
-                "state": {
-                    "height": self.state.height,
-                    "totalWork": self.state.totalWork,
-                    "hash": self.state.hash.to_owned(),
-                    "outputs": self.state.outputs
-                }
-            });
-            println!("{}", j.to_string());
+        match self.state_json() {
+            Ok(j) => println!("{}", j),
+            Err(e) => {
+                println!("{}", json!({ "error": e.to_string() }));
+                return false;
+            }
         }
         true
     }
+    /// Returns the current state of the network as the JSON object printed by [state](#method.state).
+    pub fn state_json(&self) -> Result<Value, ValidationError> {
+        if self.heads.is_empty() {
+            return Err(ValidationError::NotInitialized);
+        }
+        let outputs: Vec<&Output> = self
+            .utxos()
+            .into_iter()
+            .flat_map(UtxoSet::outputs)
+            .collect();
+        Ok(json!({
+            "state": {
+                "height": self.state.height,
+                "totalWork": self.state.totalWork,
+                "hash": self.state.hash.to_owned(),
+                "outputs": outputs
+            }
+        }))
+    }
     /// Prints a list of all current heads (possible forks) in the network.
     ///
     /// Returns `false` if a genesis block has not yet been initialized, otherwise returns `true`.
     pub fn heads(&mut self) -> bool {
-        if self.heads.len() == 0 {
-            println!("{{\"error\":\"must initialize first\"}}");
-            return false;
+        match self.heads_json() {
+            Ok(j) => println!("{}", j),
+            Err(e) => {
+                println!("{}", json!({ "error": e.to_string() }));
+                return false;
+            }
+        }
+        true
+    }
+    /// Returns the current heads as the JSON object printed by [heads](#method.heads).
+    pub fn heads_json(&self) -> Result<Value, ValidationError> {
+        if self.heads.is_empty() {
+            return Err(ValidationError::NotInitialized);
         }
         let mut heads = vec![];
         for h in &self.heads {
@@ -436,10 +1707,146 @@ impl Network {
             });
         }
 
-        let j = json!({ "heads": heads });
-        println!("{}", j.to_string());
-
-        true
+        Ok(json!({ "heads": heads }))
+    }
+    /// Returns the children of every known block, those on the main chain first, then in the
+    /// order they were received.
+    fn children(&self) -> HashMap<BlockHash, Vec<&IndexedBlock>> {
+        let mut children: HashMap<BlockHash, Vec<&IndexedBlock>> = HashMap::new();
+        for indexed in self.block_index.values() {
+            children
+                .entry(indexed.header.predecessor)
+                .or_default()
+                .push(indexed);
+        }
+        for blocks in children.values_mut() {
+            blocks.sort_by_key(|indexed| {
+                (
+                    !self.blocks_set.contains(&indexed.header.hash),
+                    indexed.received_at,
+                    indexed.header.hash,
+                )
+            });
+        }
+        children
+    }
+    /// Returns the branch of the block tree starting with the given block: the blocks up to the
+    /// next fork, and the branches starting after it.
+    fn branch_json(
+        &self,
+        first: &IndexedBlock,
+        children: &HashMap<BlockHash, Vec<&IndexedBlock>>,
+    ) -> Value {
+        let mut blocks = vec![];
+        let mut indexed = first;
+        loop {
+            let hash = indexed.header.hash;
+            let mut block = json!({
+                "hash": hash,
+                "height": indexed.height,
+                "totalWork": indexed.total_work,
+                "recent": self.recent_blocks.contains_key(&hash),
+            });
+            if self.forks.contains_key(&hash) {
+                block["head"] = json!(true);
+            }
+            if indexed.pruned {
+                block["pruned"] = json!(true);
+            }
+            blocks.push(block);
+            match children.get(&hash).map(Vec::as_slice) {
+                Some([child]) => indexed = child,
+                _ => break,
+            }
+        }
+        let branches: Vec<Value> = children
+            .get(&indexed.header.hash)
+            .into_iter()
+            .flatten()
+            .map(|child| self.branch_json(child, children))
+            .collect();
+        json!({
+            "mainChain": self.blocks_set.contains(&first.header.hash),
+            "blocks": blocks,
+            "branches": branches,
+        })
+    }
+    /// Returns the tree of all the blocks known to the network as nested JSON.
+    ///
+    /// The tree is made of branches: runs of blocks without forks, followed by the branches
+    /// forking off their last block. Each branch tells whether it is on the `mainChain`, branches
+    /// off the main chain being orphaned. Each block has its `height`, `totalWork`, whether it is
+    /// in the [recent blocks](#structfield.recent_blocks) cache and, for heads, `"head": true`,
+    /// and for [pruned](#method.prune) blocks, `"pruned": true`.
+    ///
+    /// Returns `null` if the network was not initialized.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, Network};
+    /// let mut network = Network::new(2);
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// network.init(genesis.clone()).unwrap();
+    /// network.generate(2, 100, None).unwrap();
+    /// network.generate(1, 200, Some(&genesis.hash)).unwrap();
+    ///
+    /// let tree = network.tree_json();
+    /// assert_eq!(tree["blocks"].as_array().unwrap().len(), 1);
+    /// assert_eq!(tree["branches"][0]["mainChain"], true);
+    /// assert_eq!(tree["branches"][0]["blocks"][1]["head"], true);
+    /// assert_eq!(tree["branches"][1]["mainChain"], false);
+    /// assert!(network.tree_dot().starts_with("digraph blocks {"));
+    /// ```
+    pub fn tree_json(&self) -> Value {
+        let children = self.children();
+        match self
+            .blocks
+            .first()
+            .and_then(|hash| self.block_index.get(hash))
+        {
+            Some(first) => self.branch_json(first, &children),
+            None => Value::Null,
+        }
+    }
+    /// Returns the tree of all the blocks known to the network as a Graphviz DOT graph, with
+    /// edges from each block to its successors.
+    ///
+    /// Blocks on the main chain are filled, orphaned blocks are dashed, blocks in the
+    /// [recent blocks](#structfield.recent_blocks) cache have a double border and the labels
+    /// of heads show their height and totalWork.
+    pub fn tree_dot(&self) -> String {
+        let mut blocks: Vec<&IndexedBlock> = self.block_index.values().collect();
+        blocks.sort_by_key(|indexed| (indexed.height, indexed.received_at, indexed.header.hash));
+        let mut dot = String::from("digraph blocks {\n    rankdir=LR;\n    node [shape=box];\n");
+        for indexed in &blocks {
+            let hash = indexed.header.hash;
+            let mut label = format!("{}\\n{}", indexed.height, &hash.to_string()[..10]);
+            if self.forks.contains_key(&hash) {
+                label += &format!("\\nhead, totalWork {}", indexed.total_work);
+            }
+            let mut attrs = vec![format!("label=\"{}\"", label)];
+            if self.blocks_set.contains(&hash) {
+                attrs.push(String::from("style=filled, fillcolor=lightblue"));
+            } else {
+                attrs.push(String::from("style=dashed, color=gray50"));
+            }
+            if self.recent_blocks.contains_key(&hash) {
+                attrs.push(String::from("peripheries=2"));
+            }
+            dot += &format!("    \"{}\" [{}];\n", hash, attrs.join(", "));
+        }
+        for indexed in &blocks {
+            if self.block_index.contains_key(&indexed.header.predecessor) {
+                dot += &format!(
+                    "    \"{}\" -> \"{}\";\n",
+                    indexed.header.predecessor, indexed.header.hash
+                );
+            }
+        }
+        dot += "}\n";
+        dot
     }
     /// Prints all the details of the [Network](struct.Network.html).
     pub fn print_details(&mut self) -> bool {
@@ -448,34 +1855,142 @@ impl Network {
         let j = json!({
             "recent_blocks_queue": self.recent_blocks_queue
         });
-        println!("{}", j.to_string());
+        println!("{}", j);
         let mut rbs = vec![];
         for rb in self.recent_blocks.keys() {
             rbs.push(rb);
         }
         let j2 = json!({ "recent_blocks": rbs });
-        println!("{}", j2.to_string());
+        println!("{}", j2);
         let mut forks = vec![];
         for f in self.forks.keys() {
             forks.push(f);
         }
         let j3 = json!({ "forks": forks });
-        println!("{}", j3.to_string());
+        println!("{}", j3);
 
         let mut blocks = vec![];
-        for b in &self.blocks {
-            blocks.push(b.0.hash.to_owned());
+        for hash in &self.blocks {
+            blocks.push(hash.to_owned());
         }
         let j4 = json!({ "blocks": blocks });
-        println!("{}", j4.to_string());
+        println!("{}", j4);
 
         let mut blocks_set = vec![];
         for bs in &self.blocks_set {
             blocks_set.push(bs);
         }
         let j5 = json!({ "blocks_set": blocks_set });
-        println!("{}", j5.to_string());
+        println!("{}", j5);
 
         true
     }
+    /// Returns all the details printed by [print_details](#method.print_details) as a single
+    /// JSON object.
+    pub fn details_json(&self) -> Value {
+        let blocks = &self.blocks;
+        json!({
+            "state": self.state_json().ok().map(|j| j["state"].clone()),
+            "heads": self.heads_json().ok().map(|j| j["heads"].clone()),
+            "recent_blocks_queue": self.recent_blocks_queue,
+            "recent_blocks": self.recent_blocks.keys().collect::<Vec<_>>(),
+            "forks": self.forks.keys().collect::<Vec<_>>(),
+            "blocks": blocks,
+            "blocks_set": self.blocks_set,
+            "snapshot": self.snapshot
+        })
+    }
+}
+
+/// Returns the JSON object of an unspent output with the `height` of its block and whether it is
+/// a `coinbase`.
+fn utxo_json((output, entry): (&Output, &UtxoEntry)) -> Value {
+    json!({
+        "id": output.id,
+        "amount": output.amount,
+        "height": entry.height,
+        "coinbase": entry.coinbase,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hashable;
+
+    fn network() -> Network {
+        let params = ChainParams {
+            max_reorg_depth: 2,
+            ..ChainParams::regtest()
+        };
+        let mut network = Network::with_params(params, Box::new(SystemClock));
+        let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+        genesis.hash = genesis.hash();
+        network.init(genesis).unwrap();
+        network
+    }
+
+    #[test]
+    fn pruning_drops_bodies_and_undo_data() {
+        let mut network = network();
+        let hashes = network.generate(5, 100, None).unwrap();
+        network.prune_depth = Some(0);
+        network.prune();
+
+        let pruned = network.indexed_block(&hashes[2]).unwrap();
+        assert!(pruned.pruned);
+        assert!(pruned.transactions.is_empty());
+        assert_eq!(pruned.undo, UtxoUndo::default());
+        assert!(pruned.block().is_none());
+        let kept = network.indexed_block(&hashes[3]).unwrap();
+        assert!(!kept.pruned);
+        assert!(!kept.undo.created.is_empty());
+        assert_eq!(network.get_block(&hashes[3]).unwrap().header(), kept.header);
+
+        assert_eq!(
+            network.utxo_diff(&hashes[4], &hashes[1]),
+            Err(ValidationError::BlockPruned)
+        );
+        assert_eq!(
+            network.generate(1, 200, Some(&hashes[1])),
+            Err((vec![], ValidationError::BlockPruned))
+        );
+        assert!(network.generate(1, 300, Some(&hashes[3])).is_ok());
+    }
+
+    #[test]
+    fn headers_follow_the_first_locator_hash_on_the_main_chain() {
+        let mut network = network();
+        let main = network.generate(4, 100, None).unwrap();
+        let fork = network.generate(1, 200, Some(&main[0])).unwrap();
+        let hashes = |locator: &[BlockHash], max| -> Vec<BlockHash> {
+            let headers = network.headers_after(locator, max);
+            headers.iter().map(|header| header.hash).collect()
+        };
+        assert_eq!(
+            hashes(&[BlockHash([7; 32]), fork[0], main[1]], 10),
+            main[2..]
+        );
+        assert_eq!(hashes(&[fork[0]], 2), main[..2]);
+        assert!(hashes(&[main[3]], 10).is_empty());
+    }
+
+    #[test]
+    fn pruning_visits_each_block_once() {
+        let mut network = network();
+        network.prune_depth = Some(0);
+        let main = network.generate(4, 100, None).unwrap();
+        let fork = network.generate(1, 200, Some(&main[2])).unwrap();
+        assert_eq!(network.unpruned.keys().copied().collect::<Vec<_>>(), [4, 5]);
+        assert_eq!(network.unpruned[&5], vec![main[3], fork[0]]);
+
+        network.generate(2, 300, None).unwrap();
+        assert_eq!(network.unpruned.keys().copied().collect::<Vec<_>>(), [6, 7]);
+        assert!(network.indexed_block(&fork[0]).unwrap().pruned);
+        assert!(network
+            .blocks
+            .iter()
+            .take(5)
+            .all(|hash| network.block_index[hash].pruned));
+    }
 }
@@ -0,0 +1,837 @@
+use super::{
+    BanList, Block, BlockHash, BlockHeader, ChainParams, Decode, DecodeError, Encode, HeaderSync,
+    Network, PeerInfo, ValidationError, BAN_DURATION, BAN_SCORE, MAX_HEADERS, MAX_INBOUND_PEERS,
+    MAX_OUTBOUND_PEERS,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// The version of the peer-to-peer protocol spoken by this client.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// The size of a message header: magic, command, payload length and checksum.
+pub const HEADER_SIZE: usize = 24;
+/// The largest payload accepted before the handshake completes, which only allows `version` and
+/// `verack` messages.
+pub const MAX_HANDSHAKE_PAYLOAD_SIZE: usize = 64;
+/// The largest encoded [block header](struct.BlockHeader.html): the encoding version, the
+/// difficulty, the nonce and four hashes.
+const MAX_ENCODED_HEADER_SIZE: usize = 1 + 4 + 8 + 4 * 32;
+/// How long a peer may take to accept a message before the connection is closed, so that a
+/// peer that stops reading doesn't hold up the others.
+pub const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long a peer may take to send the rest of a message once it started it, or to answer the
+/// handshake. A peer may stay silent between two messages for any time.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A struct that is exchanged by two peers at the start of a connection.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VersionPayload {
+    pub version: u32,
+    /// Hash of the genesis block of the sender, zero if it has not been initialized.
+    pub genesis: BlockHash,
+}
+
+/// A struct that requests the headers following the first known hash of a
+/// [locator](struct.Network.html#method.locator).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct GetHeadersPayload {
+    pub locator: Vec<BlockHash>,
+}
+
+impl Encode for VersionPayload {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.version.encode_to(out);
+        self.genesis.encode_to(out);
+    }
+}
+
+impl Decode for VersionPayload {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(VersionPayload {
+            version: u32::decode_from(input)?,
+            genesis: BlockHash::decode_from(input)?,
+        })
+    }
+}
+
+impl Encode for GetHeadersPayload {
+    fn encode_to(&self, out: &mut Vec<u8>) {
+        self.locator.encode_to(out);
+    }
+}
+
+impl Decode for GetHeadersPayload {
+    fn decode_from(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(GetHeadersPayload {
+            locator: Vec::decode_from(input)?,
+        })
+    }
+}
+
+/// A message of the peer-to-peer protocol.
+///
+/// On the wire every message is framed as:
+/// * 4 bytes of [magic](struct.ChainParams.html#structfield.magic), used to detect garbage and
+///   foreign chains.
+/// * 12 bytes of NUL-padded ASCII command name.
+/// * the payload length as a little-endian `u32`.
+/// * the first 4 bytes of the double SHA256 of the payload.
+/// * the [encoded](trait.Encode.html) payload.
+#[derive(Clone, Debug)]
+pub enum Message {
+    /// Opens the handshake, announcing the protocol version and the genesis block.
+    Version(VersionPayload),
+    /// Acknowledges a valid `Version`.
+    Verack,
+    /// Announces block hashes the sender has accepted.
+    Inv(Vec<BlockHash>),
+    /// Requests the blocks with the given hashes.
+    GetData(Vec<BlockHash>),
+    /// Carries a block, in response to `GetData`.
+    Block(Block),
+    /// Requests the headers of the main chain of the peer after the locator.
+    GetHeaders(GetHeadersPayload),
+    /// Carries up to [MAX_HEADERS](constant.MAX_HEADERS.html) headers, in response to `GetHeaders`.
+    Headers(Vec<BlockHeader>),
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+/// Returns the largest payload accepted from a peer that completed the handshake: a block of
+/// the [maximum size](struct.ChainParams.html#structfield.max_block_size) of the chain, or a
+/// `headers` message with [MAX_HEADERS](constant.MAX_HEADERS.html) headers if it is larger.
+pub fn max_payload_size(params: &ChainParams) -> usize {
+    let headers = 4 + MAX_HEADERS * MAX_ENCODED_HEADER_SIZE;
+    params.max_block_size.max(headers)
+}
+
+/// Returns the misbehavior carried by an error of [Message::read_from](struct.Message.html#method.read_from),
+/// if any.
+fn misbehavior(error: &io::Error) -> Option<ValidationError> {
+    error
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<ValidationError>())
+        .copied()
+}
+
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let first = crypto_hash::digest(crypto_hash::Algorithm::SHA256, payload);
+    let second = crypto_hash::digest(crypto_hash::Algorithm::SHA256, &first);
+    [second[0], second[1], second[2], second[3]]
+}
+
+impl Message {
+    /// Returns the command name of the message.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Message::Version(_) => "version",
+            Message::Verack => "verack",
+            Message::Inv(_) => "inv",
+            Message::GetData(_) => "getdata",
+            Message::Block(_) => "block",
+            Message::GetHeaders(_) => "getheaders",
+            Message::Headers(_) => "headers",
+        }
+    }
+    fn payload(&self) -> Vec<u8> {
+        match self {
+            Message::Version(v) => v.encode(),
+            Message::Verack => vec![],
+            Message::Inv(hashes) | Message::GetData(hashes) => hashes.encode(),
+            Message::Block(block) => block.encode(),
+            Message::GetHeaders(g) => g.encode(),
+            Message::Headers(headers) => headers.encode(),
+        }
+    }
+    /// Returns the framed message, ready to be written to a peer of the chain with the given magic.
+    pub fn encode(&self, magic: &[u8; 4]) -> Vec<u8> {
+        let payload = self.payload();
+        let mut command = [0u8; 12];
+        command[..self.command().len()].copy_from_slice(self.command().as_bytes());
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + payload.len());
+        bytes.extend(magic);
+        bytes.extend(&command);
+        bytes.extend(&(payload.len() as u32).to_le_bytes());
+        bytes.extend(&checksum(&payload));
+        bytes.extend(payload);
+        bytes
+    }
+    /// Reads a single framed message, whose payload must not be larger than `max_payload`.
+    ///
+    /// Returns an error of kind `InvalidData` if the frame is malformed or doesn't start with the
+    /// given magic, its checksum does not match or the payload can't be parsed. A payload larger
+    /// than `max_payload` is rejected before it is read, with an error wrapping
+    /// [ValidationError::MessageTooLarge](enum.ValidationError.html#variant.MessageTooLarge).
+    pub fn read_from<R: Read>(
+        reader: &mut R,
+        magic: &[u8; 4],
+        max_payload: usize,
+    ) -> io::Result<Message> {
+        let mut header = [0u8; HEADER_SIZE];
+        reader.read_exact(&mut header)?;
+        if header[0..4] != magic[..] {
+            return Err(invalid_data("bad magic"));
+        }
+        let command = &header[4..16];
+        let command_len = command.iter().position(|&c| c == 0).unwrap_or(12);
+        let command = std::str::from_utf8(&command[..command_len])
+            .map_err(|_| invalid_data("bad command"))?
+            .to_owned();
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&header[16..20]);
+        let length = u32::from_le_bytes(length) as usize;
+        if length > max_payload {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                ValidationError::MessageTooLarge,
+            ));
+        }
+        let mut payload = vec![0u8; length];
+        reader.read_exact(&mut payload)?;
+        if header[20..24] != checksum(&payload) {
+            return Err(invalid_data("bad checksum"));
+        }
+
+        let parsed = match command.as_str() {
+            "version" => Decode::decode(&payload).map(Message::Version),
+            "verack" => Ok(Message::Verack),
+            "inv" => Decode::decode(&payload).map(Message::Inv),
+            "getdata" => Decode::decode(&payload).map(Message::GetData),
+            "block" => Decode::decode(&payload).map(Message::Block),
+            "getheaders" => Decode::decode(&payload).map(Message::GetHeaders),
+            "headers" => Decode::decode(&payload).map(Message::Headers),
+            _ => return Err(invalid_data("unknown command")),
+        };
+        parsed.map_err(|_| invalid_data("malformed payload"))
+    }
+    /// Writes the framed message.
+    pub fn write_to<W: Write>(&self, writer: &mut W, magic: &[u8; 4]) -> io::Result<()> {
+        writer.write_all(&self.encode(magic))?;
+        writer.flush()
+    }
+}
+
+/// A struct that connects a [Network](struct.Network.html) to other nodes over TCP.
+///
+/// Blocks submitted through [submit](#method.submit), or received from a peer, are announced
+/// to every connected peer with an `inv` message once the `Network` has accepted them. Peers
+/// fetch the announced blocks they don't know with `getdata`.
+///
+/// After the handshake both sides ask for each other's headers with `getheaders`, so a node that
+/// is behind catches up with a [headers-first synchronization](struct.HeaderSync.html).
+///
+/// # Examples
+///
+/// Two nodes on 127.0.0.1 relaying a block:
+///
+/// ```
+/// # use mycoinlib::{Block, BlockHash, Hashable, Network, Node};
+/// # use std::sync::{Arc, Mutex};
+/// # use std::{thread, time::Duration};
+/// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+/// genesis.hash = genesis.hash();
+///
+/// let mut nodes = vec![];
+/// for _ in 0..2 {
+///     let mut network = Network::new(2);
+///     network.init(genesis.clone()).unwrap();
+///     nodes.push(Node::new(Arc::new(Mutex::new(network))));
+/// }
+/// let addr = nodes[1].listen("127.0.0.1:0").unwrap();
+/// nodes[0].connect(addr).unwrap();
+///
+/// let mut block = Block::new(0, BlockHash::ZERO, 1, genesis.hash.to_owned(), vec![]);
+/// block.hash = block.hash();
+/// nodes[0].submit(block.clone()).unwrap();
+///
+/// while !nodes[1].network().lock().unwrap().contains_block(&block.hash) {
+///     thread::sleep(Duration::from_millis(10));
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Node {
+    network: Arc<Mutex<Network>>,
+    /// It maps the address of every peer that completed the handshake to its connection.
+    peers: Arc<Mutex<HashMap<SocketAddr, Peer>>>,
+    sync: Arc<Mutex<HeaderSync>>,
+    bans: Arc<Mutex<BanList>>,
+    /// The magic of the chain of the network.
+    magic: [u8; 4],
+    /// The largest payload accepted from a peer after the handshake, see
+    /// [max_payload_size](fn.max_payload_size.html).
+    max_payload: usize,
+}
+
+/// A connected peer: a stream used to close the connection, the stream used for writing, which
+/// is locked on its own so that messages are written whole without holding the list of peers,
+/// and its statistics.
+struct Peer {
+    stream: TcpStream,
+    writer: Arc<Mutex<TcpStream>>,
+    info: PeerInfo,
+}
+
+/// A reader counting the bytes read from a peer.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+impl Node {
+    /// Creates a new `Node` without any peers or bans.
+    pub fn new(network: Arc<Mutex<Network>>) -> Self {
+        Node::with_bans(network, BanList::new())
+    }
+    /// Creates a new `Node` without any peers, refusing the addresses of the ban list.
+    pub fn with_bans(network: Arc<Mutex<Network>>, bans: BanList) -> Self {
+        let (magic, max_payload) = {
+            let network = network.lock().unwrap();
+            (
+                network.params.magic_bytes(),
+                max_payload_size(&network.params),
+            )
+        };
+        Node {
+            magic,
+            max_payload,
+            network,
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            sync: Arc::new(Mutex::new(HeaderSync::new())),
+            bans: Arc::new(Mutex::new(bans)),
+        }
+    }
+    /// Returns the network shared by this node.
+    pub fn network(&self) -> &Arc<Mutex<Network>> {
+        &self.network
+    }
+    /// Returns the addresses of the connected peers.
+    pub fn peers(&self) -> Vec<SocketAddr> {
+        self.peers.lock().unwrap().keys().cloned().collect()
+    }
+    /// Returns the connected peers followed by the banned addresses.
+    pub fn peer_info(&self) -> Vec<PeerInfo> {
+        let mut info: Vec<PeerInfo> = self
+            .peers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|peer| peer.info.clone())
+            .collect();
+        let now = self.now();
+        let bans = self.bans.lock().unwrap();
+        for (address, until) in &bans.bans {
+            if *until > now {
+                info.push(PeerInfo {
+                    address: address.to_string(),
+                    inbound: false,
+                    score: BAN_SCORE,
+                    bytesIn: 0,
+                    bytesOut: 0,
+                    banned: true,
+                    bannedUntil: Some(*until),
+                });
+            }
+        }
+        info
+    }
+    /// Prints the connected peers and the banned addresses.
+    pub fn print_peers(&self) -> bool {
+        let j = json!({ "peers": self.peer_info() });
+        println!("{}", j);
+        true
+    }
+    /// Starts accepting connections on the given address in a background thread.
+    ///
+    /// Connections from banned addresses, or beyond
+    /// [MAX_INBOUND_PEERS](constant.MAX_INBOUND_PEERS.html), are closed right away.
+    ///
+    /// Returns the address the node is listening on, which is useful when binding to port 0.
+    pub fn listen<A: ToSocketAddrs>(&self, addr: A) -> io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let node = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let node = node.clone();
+                thread::spawn(move || {
+                    let _ = node.handle_connection(stream);
+                });
+            }
+        });
+        Ok(local_addr)
+    }
+    /// Connects to a peer and performs the handshake.
+    ///
+    /// Returns an error if the peer is banned, the node already has
+    /// [MAX_OUTBOUND_PEERS](constant.MAX_OUTBOUND_PEERS.html) outbound connections, the peer
+    /// can't be reached or the handshake fails.
+    pub fn connect<A: ToSocketAddrs>(&self, addr: A) -> io::Result<()> {
+        let mut stream = TcpStream::connect(addr)?;
+        set_timeouts(&stream)?;
+        self.check_connection(&stream, false)?;
+        let peer_addr = self.handshake(&mut stream, false)?;
+        self.request_headers(peer_addr, vec![])?;
+        let node = self.clone();
+        thread::spawn(move || {
+            let _ = node.read_loop(peer_addr, stream);
+        });
+        Ok(())
+    }
+    /// Submits a block to the network and relays it to the peers if it was accepted.
+    ///
+    /// Returns an error if the block was not added.
+    pub fn submit(&self, block: Block) -> Result<(), ValidationError> {
+        let hash = block.hash.to_owned();
+        self.network.lock().unwrap().submit(block)?;
+        self.broadcast(&Message::Inv(vec![hash]), None);
+        Ok(())
+    }
+    /// Mines blocks with [Network::generate](struct.Network.html#method.generate) and relays
+    /// them to the peers, including those accepted before an error.
+    ///
+    /// Returns the hashes of the mined blocks, or an error along with the hashes of the blocks
+    /// accepted before it.
+    pub fn generate(
+        &self,
+        count: u64,
+        to: u64,
+        on: Option<&BlockHash>,
+    ) -> Result<Vec<BlockHash>, (Vec<BlockHash>, ValidationError)> {
+        let result = self.network.lock().unwrap().generate(count, to, on);
+        let hashes = match &result {
+            Ok(hashes) | Err((hashes, _)) => hashes,
+        };
+        if !hashes.is_empty() {
+            self.broadcast(&Message::Inv(hashes.clone()), None);
+        }
+        result
+    }
+    /// Returns the current time of the [clock](trait.Clock.html) of the network.
+    fn now(&self) -> u128 {
+        self.network.lock().unwrap().now()
+    }
+    fn genesis(&self) -> BlockHash {
+        self.network
+            .lock()
+            .unwrap()
+            .genesis_hash()
+            .unwrap_or_default()
+    }
+    fn handle_connection(&self, mut stream: TcpStream) -> io::Result<()> {
+        set_timeouts(&stream)?;
+        self.check_connection(&stream, true)?;
+        let peer_addr = self.handshake(&mut stream, true)?;
+        self.request_headers(peer_addr, vec![])?;
+        self.read_loop(peer_addr, stream)
+    }
+    /// Refuses banned addresses and connections beyond the limits.
+    fn check_connection(&self, stream: &TcpStream, inbound: bool) -> io::Result<()> {
+        let peer_addr = stream.peer_addr()?;
+        let now = self.now();
+        if self.bans.lock().unwrap().is_banned(&peer_addr.ip(), now) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "peer is banned",
+            ));
+        }
+        let limit = if inbound {
+            MAX_INBOUND_PEERS
+        } else {
+            MAX_OUTBOUND_PEERS
+        };
+        let peers = self.peers.lock().unwrap();
+        if peers.values().filter(|p| p.info.inbound == inbound).count() >= limit {
+            return Err(io::Error::other("too many connections"));
+        }
+        Ok(())
+    }
+    /// Bans the address of a peer that sent an oversized message during the handshake, before
+    /// it had a misbehavior score.
+    ///
+    /// Returns the error of the handshake.
+    fn handshake_failed(&self, peer_addr: SocketAddr, error: io::Error) -> io::Error {
+        if misbehavior(&error).is_some_and(|e| e.misbehavior_score() >= BAN_SCORE) {
+            let now = self.now();
+            let _ = self
+                .bans
+                .lock()
+                .unwrap()
+                .ban(peer_addr.ip(), now + BAN_DURATION, now);
+        }
+        error
+    }
+    /// Increases the misbehavior score of a peer by the score of the validation error, and bans
+    /// its address once the score reaches [BAN_SCORE](constant.BAN_SCORE.html).
+    ///
+    /// Returns an error if the peer got banned, which closes the connection.
+    fn misbehaving(&self, peer_addr: SocketAddr, error: ValidationError) -> io::Result<()> {
+        let mut peers = self.peers.lock().unwrap();
+        let peer = match peers.get_mut(&peer_addr) {
+            Some(peer) => peer,
+            None => return Ok(()),
+        };
+        peer.info.score += error.misbehavior_score();
+        if peer.info.score < BAN_SCORE {
+            return Ok(());
+        }
+        let _ = peer.stream.shutdown(Shutdown::Both);
+        drop(peers);
+
+        let now = self.now();
+        self.bans
+            .lock()
+            .unwrap()
+            .ban(peer_addr.ip(), now + BAN_DURATION, now)?;
+        Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            format!("peer banned: {}", error),
+        ))
+    }
+    /// Asks a peer for the headers following the main chain, or following `known` if given.
+    fn request_headers(&self, peer_addr: SocketAddr, known: Vec<BlockHash>) -> io::Result<()> {
+        let mut locator = known;
+        locator.extend(self.network.lock().unwrap().locator());
+        self.send(
+            peer_addr,
+            &Message::GetHeaders(GetHeadersPayload { locator }),
+        )
+    }
+    /// Requests the queued blocks of the synchronization from the peers.
+    fn request_blocks(&self) {
+        let peers = self.peers();
+        let now = self.now();
+        let requests = self.sync.lock().unwrap().schedule(&peers, now);
+        for (peer_addr, hashes) in requests {
+            let _ = self.send(peer_addr, &Message::GetData(hashes));
+        }
+    }
+    /// Submits blocks received from a peer in order, and relays the accepted ones.
+    fn submit_received(&self, peer_addr: SocketAddr, blocks: Vec<Block>) -> io::Result<()> {
+        for block in blocks {
+            let hash = block.hash.to_owned();
+            let submitted = self.network.lock().unwrap().submit(block);
+            match submitted {
+                Ok(()) => self.broadcast(&Message::Inv(vec![hash]), Some(peer_addr)),
+                Err(e) => self.misbehaving(peer_addr, e)?,
+            }
+        }
+        Ok(())
+    }
+    /// Exchanges `version` and `verack` messages and registers the peer.
+    ///
+    /// The connection is rejected if the peer speaks an older protocol or has a
+    /// different genesis block.
+    fn handshake(&self, stream: &mut TcpStream, inbound: bool) -> io::Result<SocketAddr> {
+        let peer_addr = stream.peer_addr()?;
+        let read = |stream: &mut TcpStream| {
+            Message::read_from(stream, &self.magic, MAX_HANDSHAKE_PAYLOAD_SIZE)
+                .map_err(|e| self.handshake_failed(peer_addr, e))
+        };
+        let genesis = self.genesis();
+        Message::Version(VersionPayload {
+            version: PROTOCOL_VERSION,
+            genesis: genesis.to_owned(),
+        })
+        .write_to(stream, &self.magic)?;
+
+        match read(stream)? {
+            Message::Version(v) => {
+                if v.version < PROTOCOL_VERSION {
+                    return Err(invalid_data("unsupported protocol version"));
+                }
+                if v.genesis != genesis {
+                    return Err(invalid_data("genesis block mismatch"));
+                }
+            }
+            _ => return Err(invalid_data("expected version")),
+        }
+        Message::Verack.write_to(stream, &self.magic)?;
+        match read(stream)? {
+            Message::Verack => {}
+            _ => return Err(invalid_data("expected verack")),
+        }
+
+        let peer = Peer {
+            stream: stream.try_clone()?,
+            writer: Arc::new(Mutex::new(stream.try_clone()?)),
+            info: PeerInfo {
+                address: peer_addr.to_string(),
+                inbound,
+                score: 0,
+                bytesIn: 0,
+                bytesOut: 0,
+                banned: false,
+                bannedUntil: None,
+            },
+        };
+        self.peers.lock().unwrap().insert(peer_addr, peer);
+        Ok(peer_addr)
+    }
+    fn read_loop(&self, peer_addr: SocketAddr, stream: TcpStream) -> io::Result<()> {
+        let mut reader = CountingReader {
+            inner: stream,
+            count: 0,
+        };
+        let result = loop {
+            let message = match Message::read_from(&mut reader, &self.magic, self.max_payload) {
+                Ok(m) => m,
+                // an idle peer, nothing of the next message was read yet: meanwhile, blocks
+                // requested from stalled peers are requested again
+                Err(e) if is_timeout(&e) && reader.count == 0 => {
+                    self.request_blocks();
+                    continue;
+                }
+                Err(e) => match misbehavior(&e) {
+                    Some(error) => match self.misbehaving(peer_addr, error) {
+                        // the rest of the frame can't be skipped safely
+                        Ok(()) => break Err(e),
+                        Err(banned) => break Err(banned),
+                    },
+                    None => break Err(e),
+                },
+            };
+            if let Some(peer) = self.peers.lock().unwrap().get_mut(&peer_addr) {
+                peer.info.bytesIn += reader.count;
+            }
+            reader.count = 0;
+            if let Err(e) = self.handle_message(peer_addr, message) {
+                break Err(e);
+            }
+        };
+        self.peers.lock().unwrap().remove(&peer_addr);
+        self.sync.lock().unwrap().peer_disconnected(peer_addr);
+        self.request_blocks();
+        result
+    }
+    fn handle_message(&self, peer_addr: SocketAddr, message: Message) -> io::Result<()> {
+        match message {
+            Message::Inv(hashes) => {
+                let network = self.network.lock().unwrap();
+                let unknown: Vec<BlockHash> = hashes
+                    .into_iter()
+                    .filter(|h| !network.contains_block(h))
+                    .collect();
+                drop(network);
+                if !unknown.is_empty() {
+                    self.send(peer_addr, &Message::GetData(unknown))?;
+                }
+            }
+            Message::GetData(hashes) => {
+                let network = self.network.lock().unwrap();
+                let blocks: Vec<Block> =
+                    hashes.iter().filter_map(|h| network.get_block(h)).collect();
+                drop(network);
+                for block in blocks {
+                    self.send(peer_addr, &Message::Block(block))?;
+                }
+            }
+            Message::Block(block) => {
+                let (limits, pow) = {
+                    let network = self.network.lock().unwrap();
+                    (network.params.check_limits(&block), network.pow.clone())
+                };
+                if let Err(e) = limits.and_then(|()| block.validate_with(&*pow)) {
+                    return self.misbehaving(peer_addr, e);
+                }
+                let network = self.network.lock().unwrap();
+                if network.contains_block(&block.hash) {
+                    return Ok(());
+                }
+                let mut sync = self.sync.lock().unwrap();
+                if sync.is_expected(&block.hash) {
+                    let ready = sync.block_received(&network, block);
+                    drop(sync);
+                    drop(network);
+                    self.submit_received(peer_addr, ready)?;
+                    self.request_blocks();
+                } else if !network.contains_block(&block.predecessor) {
+                    // we are behind the peer, catch up from its headers
+                    drop(sync);
+                    drop(network);
+                    self.request_headers(peer_addr, vec![])?;
+                } else {
+                    drop(sync);
+                    drop(network);
+                    self.submit_received(peer_addr, vec![block])?;
+                }
+            }
+            Message::GetHeaders(payload) => {
+                let headers = self
+                    .network
+                    .lock()
+                    .unwrap()
+                    .headers_after(&payload.locator, MAX_HEADERS);
+                self.send(peer_addr, &Message::Headers(headers))?;
+            }
+            Message::Headers(headers) => {
+                let full = headers.len() == MAX_HEADERS;
+                let last = headers.last().map(|header| header.hash.to_owned());
+                let network = self.network.lock().unwrap();
+                let added = self
+                    .sync
+                    .lock()
+                    .unwrap()
+                    .add_headers(&network, peer_addr, headers);
+                drop(network);
+                if let Err(e) = added {
+                    return self.misbehaving(peer_addr, e);
+                }
+                if let (true, Some(last)) = (full, last) {
+                    self.request_headers(peer_addr, vec![last])?;
+                }
+                self.request_blocks();
+            }
+            Message::Version(_) | Message::Verack => {
+                return Err(invalid_data("unexpected handshake message"));
+            }
+        }
+        Ok(())
+    }
+    fn send(&self, peer_addr: SocketAddr, message: &Message) -> io::Result<()> {
+        let writer = match self.peers.lock().unwrap().get(&peer_addr) {
+            Some(peer) => peer.writer.clone(),
+            None => return Err(io::Error::new(io::ErrorKind::NotConnected, "unknown peer")),
+        };
+        self.write(peer_addr, &writer, message)
+    }
+    /// Sends a message to every peer, except `skip`.
+    fn broadcast(&self, message: &Message, skip: Option<SocketAddr>) {
+        let writers: Vec<(SocketAddr, Arc<Mutex<TcpStream>>)> = self
+            .peers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(addr, _)| Some(**addr) != skip)
+            .map(|(addr, peer)| (*addr, peer.writer.clone()))
+            .collect();
+        for (addr, writer) in writers {
+            let _ = self.write(addr, &writer, message);
+        }
+    }
+    /// Writes a message to the stream of a peer, without holding the list of peers.
+    ///
+    /// A failed or timed out write closes the connection, since the peer may have received part
+    /// of the message.
+    fn write(
+        &self,
+        peer_addr: SocketAddr,
+        writer: &Mutex<TcpStream>,
+        message: &Message,
+    ) -> io::Result<()> {
+        let bytes = message.encode(&self.magic);
+        let mut stream = writer.lock().unwrap();
+        if let Err(e) = stream.write_all(&bytes) {
+            let _ = stream.shutdown(Shutdown::Both);
+            return Err(e);
+        }
+        drop(stream);
+        if let Some(peer) = self.peers.lock().unwrap().get_mut(&peer_addr) {
+            peer.info.bytesOut += bytes.len() as u64;
+        }
+        Ok(())
+    }
+}
+
+/// Sets the [read](constant.READ_TIMEOUT.html) and [write](constant.WRITE_TIMEOUT.html) timeouts
+/// of a connection.
+fn set_timeouts(stream: &TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))
+}
+
+/// Returns `true` if the error is a read or write timing out.
+fn is_timeout(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hashable;
+    use std::time::Instant;
+
+    const MAGIC: [u8; 4] = *b"test";
+
+    fn block_message() -> (Message, usize) {
+        let mut block = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+        block.hash = block.hash();
+        let size = block.encode().len();
+        (Message::Block(block), size)
+    }
+
+    #[test]
+    fn payload_at_the_limit_is_read() {
+        let (message, size) = block_message();
+        let bytes = message.encode(&MAGIC);
+        assert!(Message::read_from(&mut &bytes[..], &MAGIC, size).is_ok());
+        let err = Message::read_from(&mut &bytes[..], &MAGIC, size - 1).unwrap_err();
+        assert_eq!(misbehavior(&err), Some(ValidationError::MessageTooLarge));
+    }
+
+    #[test]
+    fn oversized_payload_is_rejected_before_reading_it() {
+        let (message, _) = block_message();
+        let mut bytes = message.encode(&MAGIC);
+        bytes[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        bytes.truncate(HEADER_SIZE);
+        let err =
+            Message::read_from(&mut &bytes[..], &MAGIC, MAX_HANDSHAKE_PAYLOAD_SIZE).unwrap_err();
+        assert_eq!(misbehavior(&err), Some(ValidationError::MessageTooLarge));
+    }
+
+    #[test]
+    fn max_payload_size_fits_blocks_and_headers() {
+        let params = ChainParams::main();
+        assert_eq!(max_payload_size(&params), params.max_block_size);
+        let small = ChainParams {
+            max_block_size: 1000,
+            ..ChainParams::regtest()
+        };
+        assert_eq!(
+            max_payload_size(&small),
+            4 + MAX_HEADERS * MAX_ENCODED_HEADER_SIZE
+        );
+    }
+
+    #[test]
+    fn oversized_handshake_message_bans_the_peer() {
+        let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+        genesis.hash = genesis.hash();
+        let mut network = Network::new(2);
+        network.init(genesis).unwrap();
+        let magic = network.params.magic_bytes();
+        let node = Node::new(Arc::new(Mutex::new(network)));
+        let addr = node.listen("127.0.0.1:0").unwrap();
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let mut frame = Message::Verack.encode(&magic);
+        frame[16..20].copy_from_slice(&(MAX_HANDSHAKE_PAYLOAD_SIZE as u32 + 1).to_le_bytes());
+        stream.write_all(&frame).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !node.peer_info().iter().any(|peer| peer.banned) {
+            assert!(Instant::now() < deadline, "peer was not banned");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
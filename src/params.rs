@@ -0,0 +1,395 @@
+use super::{pow_hasher, Block, BlockHash, Encode, PowHasher, ValidationError};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// A struct that represents the block subsidy schedule: the coinbase transactions of a block may
+/// create outputs worth up to `initial`, halved every `halving_interval` blocks.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Subsidy {
+    pub initial: u64,
+    /// The number of blocks between two halvings, `0` to never halve.
+    pub halving_interval: u64,
+}
+
+/// A struct that holds the consensus rules of a chain.
+///
+/// Nodes only agree on a chain if they use the same parameters. The built-in presets are
+/// [main](#method.main), [test](#method.test) and [regtest](#method.regtest); other chains can be
+/// [loaded](#method.load) from a TOML file with the same fields, e.g. the following. Missing size
+/// limits and `max_reorg_depth` take their values on the main chain, and missing `generate` and
+/// `utxo_commitment` are `false`.
+///
+/// ```toml
+/// name = "cheap"
+/// pow = "blake3"
+/// min_difficulty = 1
+/// target_spacing = 60000
+/// retarget_window = 100
+/// max_block_size = 1000000
+/// max_block_transactions = 10000
+/// max_transaction_inputs = 1000
+/// max_transaction_outputs = 1000
+/// coinbase_maturity = 10
+/// magic = "chea"
+/// recent_count_limit = 2
+///
+/// [subsidy]
+/// initial = 100
+/// halving_interval = 1000
+/// ```
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::{ChainParams, Network};
+/// let params = ChainParams::named("test").unwrap();
+/// assert_eq!(params.block_subsidy(1), params.subsidy.initial);
+///
+/// let network = Network::with_params(params, Box::new(mycoinlib::SystemClock));
+/// assert_eq!(network.state.height, 1);
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChainParams {
+    /// The name of the chain, also used as the name of its data directory.
+    pub name: String,
+    /// The genesis block the network starts with. Without one, the network must be initialized
+    /// with [Network::init](struct.Network.html#method.init) and is bound to the first genesis
+    /// block it accepts.
+    pub genesis: Option<Block>,
+    /// The name of the proof-of-work algorithm, see [pow_hasher](fn.pow_hasher.html).
+    pub pow: String,
+    /// The lowest difficulty of a block.
+    pub min_difficulty: u32,
+    /// The expected time between two blocks, in milliseconds.
+    ///
+    /// Difficulty retargeting is not implemented yet: this value and
+    /// [retarget_window](#structfield.retarget_window) are checked when the parameters are
+    /// [loaded](#method.load) but not enforced, and a block only needs at least the difficulty
+    /// of its predecessor and the [min_difficulty](#structfield.min_difficulty).
+    pub target_spacing: u64,
+    /// The number of blocks after which miners should reconsider the difficulty, so that blocks
+    /// keep coming every `target_spacing` on average. Not enforced yet, see
+    /// [target_spacing](#structfield.target_spacing).
+    pub retarget_window: u64,
+    pub subsidy: Subsidy,
+    /// The largest [encoded](trait.Encode.html) size of a block, in bytes.
+    #[serde(default = "default_max_block_size")]
+    pub max_block_size: usize,
+    /// The largest number of transactions in a block.
+    #[serde(default = "default_max_block_transactions")]
+    pub max_block_transactions: usize,
+    /// The largest number of inputs of a transaction.
+    #[serde(default = "default_max_transaction_inputs")]
+    pub max_transaction_inputs: usize,
+    /// The largest number of outputs of a transaction.
+    #[serde(default = "default_max_transaction_outputs")]
+    pub max_transaction_outputs: usize,
+    /// The number of blocks after which the outputs of a coinbase transaction can be spent.
+    pub coinbase_maturity: u64,
+    /// The four ASCII characters every peer-to-peer message starts with.
+    pub magic: String,
+    /// See [Network::new](struct.Network.html#method.new).
+    pub recent_count_limit: usize,
+    /// Whether blocks can be mined on demand with
+    /// [Network::generate](struct.Network.html#method.generate).
+    #[serde(default)]
+    pub generate: bool,
+    /// Whether every block must commit to the [root](struct.UtxoSet.html#method.root) of the
+    /// unspent outputs after it in its [utxo_root](struct.Block.html#structfield.utxo_root).
+    #[serde(default)]
+    pub utxo_commitment: bool,
+    /// The deepest reorganization nodes are expected to follow: a
+    /// [pruned](struct.Network.html#structfield.prune_depth) node keeps the bodies of at least
+    /// this many blocks below the main chain head, so it can still serve them to peers switching
+    /// branches.
+    #[serde(default = "default_max_reorg_depth")]
+    pub max_reorg_depth: u64,
+}
+
+fn default_max_block_size() -> usize {
+    ChainParams::main().max_block_size
+}
+
+fn default_max_block_transactions() -> usize {
+    ChainParams::main().max_block_transactions
+}
+
+fn default_max_transaction_inputs() -> usize {
+    ChainParams::main().max_transaction_inputs
+}
+
+fn default_max_transaction_outputs() -> usize {
+    ChainParams::main().max_transaction_outputs
+}
+
+fn default_max_reorg_depth() -> u64 {
+    ChainParams::main().max_reorg_depth
+}
+
+impl ChainParams {
+    /// Returns the parameters of the main chain.
+    pub fn main() -> Self {
+        ChainParams {
+            name: String::from("main"),
+            genesis: Some(Block::new(
+                5,
+                "0x00000ef6e65ba32c69652528182bdf3e89cea4a459cdb65bda1df59d5ec09e04"
+                    .parse()
+                    .unwrap(),
+                87110,
+                BlockHash::ZERO,
+                vec![],
+            )),
+            pow: String::from("sha256"),
+            min_difficulty: 5,
+            target_spacing: 600_000,
+            retarget_window: 2016,
+            subsidy: Subsidy {
+                initial: 5_000_000_000,
+                halving_interval: 210_000,
+            },
+            max_block_size: 1_000_000,
+            max_block_transactions: 10_000,
+            max_transaction_inputs: 1_000,
+            max_transaction_outputs: 1_000,
+            coinbase_maturity: 100,
+            magic: String::from("myco"),
+            recent_count_limit: 2,
+            generate: false,
+            utxo_commitment: false,
+            max_reorg_depth: 100,
+        }
+    }
+    /// Returns the parameters of the test chain, which is cheaper to mine than the main chain.
+    pub fn test() -> Self {
+        ChainParams {
+            name: String::from("test"),
+            genesis: Some(Block::new(
+                3,
+                "0x00012ab7a8384dea1a4fa6f607e57f72d1f7d8e84afb9ea430da6dc2a71b0af0"
+                    .parse()
+                    .unwrap(),
+                13390,
+                BlockHash::ZERO,
+                vec![],
+            )),
+            pow: String::from("blake3"),
+            min_difficulty: 3,
+            magic: String::from("mtst"),
+            ..ChainParams::main()
+        }
+    }
+    /// Returns the parameters of a local regression test chain: no fixed genesis block, no
+    /// minimum difficulty, a subsidy halving every 150 blocks and blocks mined on demand.
+    pub fn regtest() -> Self {
+        ChainParams {
+            name: String::from("regtest"),
+            genesis: None,
+            min_difficulty: 0,
+            subsidy: Subsidy {
+                initial: 5_000_000_000,
+                halving_interval: 150,
+            },
+            magic: String::from("mreg"),
+            generate: true,
+            ..ChainParams::main()
+        }
+    }
+    /// Returns the built-in parameters with the given name: `main`, `test` or `regtest`.
+    pub fn named(name: &str) -> Option<Self> {
+        match name {
+            "main" => Some(ChainParams::main()),
+            "test" => Some(ChainParams::test()),
+            "regtest" => Some(ChainParams::regtest()),
+            _ => None,
+        }
+    }
+    /// Loads the parameters from a TOML file.
+    ///
+    /// Returns an error of kind `InvalidData` if the file can't be parsed, names an unknown
+    /// proof-of-work algorithm, has an invalid genesis block, a `target_spacing` or
+    /// `retarget_window` of `0`, or its magic is not four bytes long.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let data = fs::read_to_string(path)?;
+        let params: ChainParams = toml::from_str(&data).map_err(invalid_data)?;
+        let hasher = match pow_hasher(&params.pow) {
+            Some(hasher) => hasher,
+            None => return Err(invalid_data("unknown proof-of-work algorithm")),
+        };
+        if let Some(genesis) = &params.genesis {
+            genesis.check_genesis().map_err(invalid_data)?;
+            genesis.validate_with(&*hasher).map_err(invalid_data)?;
+        }
+        if params.target_spacing == 0 || params.retarget_window == 0 {
+            return Err(invalid_data(
+                "target_spacing and retarget_window must be positive",
+            ));
+        }
+        if params.magic.len() != 4 {
+            return Err(invalid_data("magic must be four bytes long"));
+        }
+        Ok(params)
+    }
+    /// Returns the proof-of-work algorithm of the chain.
+    ///
+    /// Panics if the algorithm is unknown.
+    pub fn hasher(&self) -> Arc<dyn PowHasher> {
+        pow_hasher(&self.pow).expect("unknown proof-of-work algorithm")
+    }
+    /// Returns the magic bytes of the peer-to-peer messages.
+    ///
+    /// Panics if the magic is not four bytes long.
+    pub fn magic_bytes(&self) -> [u8; 4] {
+        let mut magic = [0u8; 4];
+        magic.copy_from_slice(self.magic.as_bytes());
+        magic
+    }
+    /// Checks the block against the size limits of the chain.
+    ///
+    /// These checks are cheap, so they run before any other validation of a block.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, ChainParams, Transaction, ValidationError};
+    /// let params = ChainParams {
+    ///     max_block_transactions: 1,
+    ///     ..ChainParams::regtest()
+    /// };
+    /// let empty = Transaction {
+    ///     inputs: vec![],
+    ///     outputs: vec![],
+    /// };
+    /// let block = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![empty; 2]);
+    /// assert_eq!(
+    ///     params.check_limits(&block),
+    ///     Err(ValidationError::TooManyTransactions)
+    /// );
+    /// ```
+    pub fn check_limits(&self, block: &Block) -> Result<(), ValidationError> {
+        if block.transactions.len() > self.max_block_transactions {
+            return Err(ValidationError::TooManyTransactions);
+        }
+        for transaction in &block.transactions {
+            if transaction.inputs.len() > self.max_transaction_inputs {
+                return Err(ValidationError::TooManyInputs);
+            }
+            if transaction.outputs.len() > self.max_transaction_outputs {
+                return Err(ValidationError::TooManyOutputs);
+            }
+        }
+        if block.encode().len() > self.max_block_size {
+            return Err(ValidationError::BlockTooLarge);
+        }
+        Ok(())
+    }
+    /// Returns the largest amount the coinbase transactions of the block at the given height may
+    /// create, the genesis block being at height 1.
+    pub fn block_subsidy(&self, height: u64) -> u64 {
+        if self.subsidy.halving_interval == 0 {
+            return self.subsidy.initial;
+        }
+        let halvings = height.saturating_sub(1) / self.subsidy.halving_interval;
+        if halvings >= 64 {
+            return 0;
+        }
+        self.subsidy.initial >> halvings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Output, Transaction};
+
+    fn load_toml(name: &str, toml: &str) -> io::Result<ChainParams> {
+        let path = std::env::temp_dir().join(format!("mycoin-params-{}.toml", name));
+        fs::write(&path, toml).unwrap();
+        let params = ChainParams::load(&path);
+        fs::remove_file(path).unwrap();
+        params
+    }
+
+    fn cheap_toml(target_spacing: u64, retarget_window: u64) -> String {
+        format!(
+            "name = \"cheap\"\npow = \"blake3\"\nmin_difficulty = 1\n\
+             target_spacing = {}\nretarget_window = {}\ncoinbase_maturity = 10\n\
+             magic = \"chea\"\nrecent_count_limit = 2\n\
+             [subsidy]\ninitial = 100\nhalving_interval = 1000\n",
+            target_spacing, retarget_window
+        )
+    }
+
+    #[test]
+    fn load_keeps_retarget_parameters() {
+        let params = load_toml("retarget", &cheap_toml(60_000, 100)).unwrap();
+        assert_eq!(params.target_spacing, 60_000);
+        assert_eq!(params.retarget_window, 100);
+    }
+
+    #[test]
+    fn load_rejects_zero_retarget_parameters() {
+        for (name, toml) in [
+            ("zero-spacing", cheap_toml(0, 100)),
+            ("zero-window", cheap_toml(60_000, 0)),
+        ] {
+            let err = load_toml(name, &toml).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    fn transaction(inputs: usize, outputs: usize) -> Transaction {
+        let output = |id| Output { id, amount: 1 };
+        Transaction {
+            inputs: (0..inputs as u64).map(output).collect(),
+            outputs: (0..outputs as u64).map(output).collect(),
+        }
+    }
+
+    #[test]
+    fn limits_allow_blocks_at_the_boundary() {
+        let block = |transactions| Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, transactions);
+        let full = block(vec![transaction(3, 4); 2]);
+        let params = ChainParams {
+            max_block_size: full.encode().len(),
+            max_block_transactions: 2,
+            max_transaction_inputs: 3,
+            max_transaction_outputs: 4,
+            ..ChainParams::regtest()
+        };
+        assert_eq!(params.check_limits(&full), Ok(()));
+
+        let cases = [
+            (
+                block(vec![transaction(3, 4); 3]),
+                ValidationError::TooManyTransactions,
+            ),
+            (
+                block(vec![transaction(4, 4)]),
+                ValidationError::TooManyInputs,
+            ),
+            (
+                block(vec![transaction(3, 5)]),
+                ValidationError::TooManyOutputs,
+            ),
+        ];
+        for (block, error) in cases.iter() {
+            assert_eq!(params.check_limits(block), Err(*error));
+        }
+        let small = ChainParams {
+            max_block_size: full.encode().len() - 1,
+            ..params
+        };
+        assert_eq!(
+            small.check_limits(&full),
+            Err(ValidationError::BlockTooLarge)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Misbehavior score at which a peer gets banned.
+pub const BAN_SCORE: u32 = 100;
+/// How long a ban lasts, in milliseconds.
+pub const BAN_DURATION: u128 = 24 * 60 * 60 * 1000;
+/// Maximum number of connections accepted from other nodes.
+pub const MAX_INBOUND_PEERS: usize = 32;
+/// Maximum number of connections opened to other nodes.
+pub const MAX_OUTBOUND_PEERS: usize = 8;
+
+/// A struct that represents a connected (or banned) peer, as shown by the `peers` query.
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PeerInfo {
+    pub address: String,
+    /// `true` if the peer connected to us.
+    pub inbound: bool,
+    /// The misbehavior score of the peer, see [BAN_SCORE](constant.BAN_SCORE.html).
+    pub score: u32,
+    pub bytesIn: u64,
+    pub bytesOut: u64,
+    pub banned: bool,
+    /// The unix timestamp (in milliseconds) at which the ban of the peer expires.
+    pub bannedUntil: Option<u128>,
+}
+
+/// A struct that represents a single ban in the ban list file.
+#[derive(Serialize, Deserialize)]
+struct Ban {
+    address: IpAddr,
+    until: u128,
+}
+
+/// A struct that keeps track of the temporarily banned peer addresses.
+///
+/// If the list was [loaded](#method.load) from a file, every change is written back to it so
+/// that bans survive a restart.
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::{now, BanList, BAN_DURATION};
+/// let path = std::env::temp_dir().join("mycoin-banlist-example.json");
+/// let address = "10.0.0.1".parse().unwrap();
+///
+/// let mut bans = BanList::load(&path).unwrap();
+/// bans.ban(address, now() + BAN_DURATION, now()).unwrap();
+///
+/// let bans = BanList::load(&path).unwrap();
+/// assert!(bans.is_banned(&address, now()));
+/// # std::fs::remove_file(path).unwrap();
+/// ```
+#[derive(Default)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    /// It maps every banned address to the unix timestamp at which its ban expires.
+    pub bans: HashMap<IpAddr, u128>,
+}
+
+impl BanList {
+    /// Creates a new, empty `BanList` which is not persisted.
+    pub fn new() -> Self {
+        BanList::default()
+    }
+    /// Loads the ban list from a JSON file, which is created on the first ban if it doesn't exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut bans = HashMap::new();
+        match fs::read(&path) {
+            Ok(data) => {
+                let list: Vec<Ban> = serde_json::from_slice(&data)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                for ban in list {
+                    bans.insert(ban.address, ban.until);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(BanList {
+            path: Some(path),
+            bans,
+        })
+    }
+    /// Returns `true` if the address is banned at the given time.
+    pub fn is_banned(&self, address: &IpAddr, now: u128) -> bool {
+        match self.bans.get(address) {
+            Some(until) => *until > now,
+            None => false,
+        }
+    }
+    /// Bans an address until the given time, forgetting the expired bans.
+    pub fn ban(&mut self, address: IpAddr, until: u128, now: u128) -> io::Result<()> {
+        self.bans.retain(|_, u| *u > now);
+        self.bans.insert(address, until);
+        self.save()
+    }
+    /// Writes the ban list to its file, if any.
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let list: Vec<Ban> = self
+            .bans
+            .iter()
+            .map(|(address, until)| Ban {
+                address: *address,
+                until: *until,
+            })
+            .collect();
+        fs::write(path, serde_json::to_vec(&list)?)
+    }
+}
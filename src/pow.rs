@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+/// A hash function used for the proof of work of blocks.
+///
+/// Block hashes are the 32-byte digest, written as 64 hex digits, so the
+/// [difficulty](struct.Block.html#structfield.difficulty) counts leading zero hex digits whatever
+/// the algorithm.
+///
+/// # Examples
+///
+/// Mining and validating a block under BLAKE3:
+///
+/// ```
+/// # use mycoinlib::{Blake3, Block, BlockHash, Hashable, ValidationError};
+/// let mut block = Block::new(1, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+/// loop {
+///     block.hash = block.hash_with(&Blake3);
+///     if block.validate_with(&Blake3).is_ok() {
+///         break;
+///     }
+///     block.nonce += 1;
+/// }
+/// assert_eq!(block.validate(), Err(ValidationError::InvalidHash));
+/// ```
+pub trait PowHasher: Send + Sync {
+    /// Returns the name of the algorithm, as accepted by [pow_hasher](fn.pow_hasher.html).
+    fn name(&self) -> &str;
+    /// Returns the digest of the bytes.
+    fn digest(&self, bytes: &[u8]) -> [u8; 32];
+}
+
+/// SHA-256, the default proof-of-work algorithm.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Sha256;
+
+impl PowHasher for Sha256 {
+    fn name(&self) -> &str {
+        "sha256"
+    }
+    fn digest(&self, bytes: &[u8]) -> [u8; 32] {
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&crypto_hash::digest(crypto_hash::Algorithm::SHA256, bytes));
+        digest
+    }
+}
+
+/// SHA-256 applied twice.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DoubleSha256;
+
+impl PowHasher for DoubleSha256 {
+    fn name(&self) -> &str {
+        "sha256d"
+    }
+    fn digest(&self, bytes: &[u8]) -> [u8; 32] {
+        Sha256.digest(&Sha256.digest(bytes))
+    }
+}
+
+/// BLAKE3, which is much cheaper to compute than SHA-256.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Blake3;
+
+impl PowHasher for Blake3 {
+    fn name(&self) -> &str {
+        "blake3"
+    }
+    fn digest(&self, bytes: &[u8]) -> [u8; 32] {
+        *blake3::hash(bytes).as_bytes()
+    }
+}
+
+/// Returns the built-in proof-of-work algorithm with the given name: `sha256`, `sha256d` or
+/// `blake3`.
+pub fn pow_hasher(name: &str) -> Option<Arc<dyn PowHasher>> {
+    match name {
+        "sha256" => Some(Arc::new(Sha256)),
+        "sha256d" => Some(Arc::new(DoubleSha256)),
+        "blake3" => Some(Arc::new(Blake3)),
+        _ => None,
+    }
+}
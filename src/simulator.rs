@@ -0,0 +1,436 @@
+use super::{pow_hasher, Block, BlockHash, ChainParams, Head, ManualClock, Network, PowHasher};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+
+/// A small seeded pseudo-random number generator (SplitMix64), so that simulations are
+/// reproducible from their seed.
+#[derive(Clone, Debug)]
+pub struct SimRng {
+    state: u64,
+}
+
+impl SimRng {
+    /// Creates a new `SimRng` instance from a seed.
+    pub fn new(seed: u64) -> Self {
+        SimRng { state: seed }
+    }
+    /// Returns the next pseudo-random `u64`.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    /// Returns a pseudo-random number in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+    /// Returns a pseudo-random number in `[min, max]`.
+    pub fn range(&mut self, min: u64, max: u64) -> u64 {
+        if max <= min {
+            return min;
+        }
+        min + self.next_u64() % (max - min + 1)
+    }
+    /// Returns an exponentially distributed number with the given mean.
+    pub fn exponential(&mut self, mean: f64) -> f64 {
+        -(1.0 - self.next_f64()).ln() * mean
+    }
+}
+
+/// A struct that configures a [Simulator](struct.Simulator.html).
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SimConfig {
+    /// The hashrate of the miner of each node, relative to the others. A node with a hashrate
+    /// of 0 doesn't mine. The number of nodes is the length of this list.
+    pub hashrates: Vec<f64>,
+    /// The expected time between two blocks of the whole network, in milliseconds.
+    pub block_interval: u64,
+    /// The minimum and maximum latency of a link between two nodes, in milliseconds.
+    pub latency: (u64, u64),
+    /// The difficulty of every block.
+    pub difficulty: u32,
+    /// The name of the proof-of-work algorithm of the chain, see
+    /// [pow_hasher](fn.pow_hasher.html).
+    pub pow: String,
+    /// The seed of the random number generator.
+    pub seed: u64,
+    /// See [Network::new](struct.Network.html#method.new).
+    pub recent_count_limit: usize,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            hashrates: vec![1.0; 4],
+            block_interval: 10_000,
+            latency: (50, 500),
+            difficulty: 1,
+            pow: String::from("sha256"),
+            seed: 0,
+            recent_count_limit: 2,
+        }
+    }
+}
+
+/// A struct that represents the outcome of a simulation.
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SimReport {
+    /// The simulated time, in milliseconds.
+    pub time: u64,
+    /// The main chain head of each node.
+    pub tips: Vec<Head>,
+    /// The number of mined blocks, excluding the genesis block.
+    pub mined: u64,
+    /// The fraction of the mined blocks that are not in the main chain of the first node.
+    pub staleRate: f64,
+    /// The time between the last [heal](struct.Simulator.html#method.heal) and the moment all
+    /// nodes agreed on the same head, in milliseconds.
+    pub convergenceTime: Option<u64>,
+}
+
+enum Event {
+    /// The miner of a node finds a block.
+    Mine(usize),
+    /// A block arrives at a node.
+    Deliver(usize, Block),
+}
+
+/// An event scheduled at a simulated time; ties are broken by scheduling order.
+struct Scheduled {
+    time: u64,
+    seq: u64,
+    event: Event,
+}
+
+impl PartialEq for Scheduled {
+    fn eq(&self, other: &Self) -> bool {
+        (self.time, self.seq) == (other.time, other.seq)
+    }
+}
+
+impl Eq for Scheduled {}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed, so that the `BinaryHeap` pops the earliest event first
+        (other.time, other.seq).cmp(&(self.time, self.seq))
+    }
+}
+
+/// A node of the simulation: its own [Network](struct.Network.html) and the blocks received
+/// before their predecessor.
+pub struct SimNode {
+    pub network: Network,
+    clock: ManualClock,
+    orphans: HashMap<BlockHash, Vec<Block>>,
+}
+
+/// A struct that runs many nodes in a single process on a simulated clock.
+///
+/// Each node has its own [Network](struct.Network.html) and, optionally, a miner finding blocks
+/// on top of its main chain after exponentially distributed delays proportional to its share of
+/// the total hashrate. Mined blocks are sent to every other node over links with a random
+/// latency. Links between nodes in different partitions hold their blocks until the partition
+/// heals. Every random choice comes from a seeded generator, and every node reads the simulated
+/// time from a [ManualClock](struct.ManualClock.html), so a simulation is fully reproducible.
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::{SimConfig, Simulator};
+/// let mut sim = Simulator::new(SimConfig {
+///     seed: 7,
+///     ..SimConfig::default()
+/// });
+/// sim.partition(&[vec![0, 1], vec![2, 3]]);
+/// sim.run_until(300_000);
+/// sim.heal();
+/// sim.run_until(400_000);
+///
+/// let report = sim.report();
+/// assert!(report.convergenceTime.is_some());
+/// assert!(report.staleRate > 0.0);
+/// ```
+pub struct Simulator {
+    pub config: SimConfig,
+    pub nodes: Vec<SimNode>,
+    /// The current simulated time, in milliseconds.
+    pub time: u64,
+    pow: Arc<dyn PowHasher>,
+    rng: SimRng,
+    events: BinaryHeap<Scheduled>,
+    seq: u64,
+    /// The partition of each node, if the network is partitioned.
+    partitions: Option<Vec<usize>>,
+    /// Blocks sent across partitions, delivered when the partition heals.
+    held: Vec<(usize, Block)>,
+    /// The hashes of all mined blocks, excluding the genesis block.
+    mined: Vec<BlockHash>,
+    healed_at: Option<u64>,
+    converged_at: Option<u64>,
+}
+
+impl Simulator {
+    /// Creates a new `Simulator` instance, with every node initialized with the same genesis block.
+    ///
+    /// Panics if the proof-of-work algorithm of the configuration is unknown.
+    pub fn new(config: SimConfig) -> Self {
+        let pow = pow_hasher(&config.pow).expect("unknown proof-of-work algorithm");
+        let mut rng = SimRng::new(config.seed);
+        let genesis = mine_block(&*pow, &mut rng, config.difficulty, BlockHash::ZERO);
+        let nodes = config
+            .hashrates
+            .iter()
+            .map(|_| {
+                let clock = ManualClock::new(0);
+                let params = ChainParams {
+                    pow: config.pow.to_owned(),
+                    recent_count_limit: config.recent_count_limit,
+                    ..ChainParams::regtest()
+                };
+                let mut network = Network::with_params(params, Box::new(clock.clone()));
+                network.init(genesis.clone()).unwrap();
+                SimNode {
+                    network,
+                    clock,
+                    orphans: HashMap::new(),
+                }
+            })
+            .collect();
+        let mut sim = Simulator {
+            config,
+            nodes,
+            time: 0,
+            pow,
+            rng,
+            events: BinaryHeap::new(),
+            seq: 0,
+            partitions: None,
+            held: vec![],
+            mined: vec![],
+            healed_at: None,
+            converged_at: None,
+        };
+        for node in 0..sim.nodes.len() {
+            sim.schedule_mining(node);
+        }
+        sim
+    }
+    fn schedule(&mut self, time: u64, event: Event) {
+        self.seq += 1;
+        self.events.push(Scheduled {
+            time,
+            seq: self.seq,
+            event,
+        });
+    }
+    fn schedule_mining(&mut self, node: usize) {
+        let total: f64 = self.config.hashrates.iter().sum();
+        let hashrate = self.config.hashrates[node];
+        if hashrate <= 0.0 {
+            return;
+        }
+        let mean = self.config.block_interval as f64 * total / hashrate;
+        let delay = self.rng.exponential(mean).ceil() as u64;
+        self.schedule(self.time + delay.max(1), Event::Mine(node));
+    }
+    /// Splits the nodes into partitions; blocks only travel between nodes of the same partition.
+    ///
+    /// Nodes missing from every partition are isolated.
+    pub fn partition(&mut self, groups: &[Vec<usize>]) {
+        let mut partitions: Vec<usize> = (0..self.nodes.len()).map(|n| groups.len() + n).collect();
+        for (group, nodes) in groups.iter().enumerate() {
+            for node in nodes {
+                partitions[*node] = group;
+            }
+        }
+        self.partitions = Some(partitions);
+    }
+    /// Removes the partitions and sends the held blocks.
+    pub fn heal(&mut self) {
+        self.partitions = None;
+        self.healed_at = Some(self.time);
+        self.converged_at = None;
+        for (to, block) in std::mem::take(&mut self.held) {
+            let latency = self.latency();
+            self.schedule(self.time + latency, Event::Deliver(to, block));
+        }
+    }
+    fn latency(&mut self) -> u64 {
+        let (min, max) = self.config.latency;
+        self.rng.range(min, max)
+    }
+    fn connected(&self, a: usize, b: usize) -> bool {
+        match &self.partitions {
+            Some(partitions) => partitions[a] == partitions[b],
+            None => true,
+        }
+    }
+    /// Processes every event scheduled up to the given simulated time.
+    pub fn run_until(&mut self, time: u64) {
+        while let Some(next) = self.events.peek() {
+            if next.time > time {
+                break;
+            }
+            let scheduled = self.events.pop().unwrap();
+            self.time = scheduled.time;
+            match scheduled.event {
+                Event::Mine(node) => self.mine(node),
+                Event::Deliver(node, block) => self.deliver(node, block),
+            }
+            if self.healed_at.is_some() && self.converged_at.is_none() && self.converged() {
+                self.converged_at = Some(self.time);
+            }
+        }
+        self.time = time;
+    }
+    fn mine(&mut self, node: usize) {
+        let tip = self.nodes[node].network.state.hash.to_owned();
+        let block = mine_block(&*self.pow, &mut self.rng, self.config.difficulty, tip);
+        self.mined.push(block.hash.to_owned());
+        self.deliver(node, block.clone());
+        for to in 0..self.nodes.len() {
+            if to == node {
+                continue;
+            }
+            if self.connected(node, to) {
+                let latency = self.latency();
+                self.schedule(self.time + latency, Event::Deliver(to, block.clone()));
+            } else {
+                self.held.push((to, block.clone()));
+            }
+        }
+        self.schedule_mining(node);
+    }
+    /// Submits a block to a node, along with the blocks that were waiting for it.
+    fn deliver(&mut self, node: usize, block: Block) {
+        let sim_node = &mut self.nodes[node];
+        sim_node.clock.set(self.time as u128);
+        if sim_node.network.contains_block(&block.hash) {
+            return;
+        }
+        if !sim_node.network.contains_block(&block.predecessor) {
+            sim_node
+                .orphans
+                .entry(block.predecessor.to_owned())
+                .or_default()
+                .push(block);
+            return;
+        }
+        let mut pending = vec![block];
+        while let Some(block) = pending.pop() {
+            let hash = block.hash.to_owned();
+            if sim_node.network.submit(block).is_ok() {
+                if let Some(children) = sim_node.orphans.remove(&hash) {
+                    pending.extend(children);
+                }
+            }
+        }
+    }
+    /// Returns `true` if every node has the same main chain head, as is the case of a simulation
+    /// without nodes.
+    pub fn converged(&self) -> bool {
+        self.nodes
+            .windows(2)
+            .all(|pair| pair[0].network.state.hash == pair[1].network.state.hash)
+    }
+    /// Returns the current tips, stale-block rate and time to convergence.
+    pub fn report(&self) -> SimReport {
+        let tips = self
+            .nodes
+            .iter()
+            .map(|n| Head {
+                height: n.network.state.height,
+                totalWork: n.network.state.totalWork,
+                hash: n.network.state.hash.to_owned(),
+            })
+            .collect();
+        let main_chain: HashSet<&BlockHash> = self
+            .nodes
+            .first()
+            .map(|n| n.network.blocks.iter().collect())
+            .unwrap_or_default();
+        let stale = self
+            .mined
+            .iter()
+            .filter(|hash| !main_chain.contains(hash))
+            .count();
+        let stale_rate = if self.mined.is_empty() {
+            0.0
+        } else {
+            stale as f64 / self.mined.len() as f64
+        };
+        let convergence_time = match (self.healed_at, self.converged_at) {
+            (Some(healed_at), Some(converged_at)) => Some(converged_at - healed_at),
+            _ => None,
+        };
+        SimReport {
+            time: self.time,
+            tips,
+            mined: self.mined.len() as u64,
+            staleRate: stale_rate,
+            convergenceTime: convergence_time,
+        }
+    }
+}
+
+/// Mines an empty block on top of `predecessor`, starting from a random nonce so that
+/// competing miners find different blocks.
+fn mine_block(
+    pow: &dyn PowHasher,
+    rng: &mut SimRng,
+    difficulty: u32,
+    predecessor: BlockHash,
+) -> Block {
+    let mut block = Block::new(
+        difficulty,
+        BlockHash::ZERO,
+        rng.next_u64(),
+        predecessor,
+        vec![],
+    );
+    block.mine(pow).unwrap();
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simulation_without_nodes_is_converged() {
+        let mut sim = Simulator::new(SimConfig {
+            hashrates: vec![],
+            ..SimConfig::default()
+        });
+        assert!(sim.converged());
+        sim.heal();
+        sim.run_until(100_000);
+        let report = sim.report();
+        assert!(report.tips.is_empty());
+        assert_eq!(report.mined, 0);
+        assert_eq!(report.convergenceTime, None);
+    }
+
+    #[test]
+    fn simulation_of_one_node_is_converged() {
+        let mut sim = Simulator::new(SimConfig {
+            hashrates: vec![1.0],
+            ..SimConfig::default()
+        });
+        sim.run_until(100_000);
+        assert!(sim.converged());
+        assert_eq!(sim.report().staleRate, 0.0);
+    }
+}
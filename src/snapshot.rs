@@ -0,0 +1,131 @@
+use super::{
+    Block, BlockHash, Encode, Hashable, Output, UtxoEntry, UtxoFilter, UtxoSet, ValidationError,
+};
+use serde::{Deserialize, Serialize};
+
+/// A struct that describes the base block of a [UTXO snapshot](struct.UtxoSnapshot.html): the
+/// block a network started from the snapshot trusts without knowing the blocks before it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotBase {
+    /// The hash of the genesis block of the chain.
+    pub genesis: BlockHash,
+    /// The hash of the base block.
+    pub hash: BlockHash,
+    /// The height of the base block, the genesis block being at height 1.
+    pub height: u64,
+    /// The totalWork of the chain ending with the base block.
+    pub total_work: u64,
+    /// The [hash](struct.UtxoSnapshot.html#impl-Hashable-for-UtxoSnapshot) of the content of the
+    /// snapshot.
+    pub content_hash: BlockHash,
+}
+
+/// A struct that holds the unspent outputs of the main chain as of a block, to start a node from
+/// that block instead of submitting every block since the genesis block.
+///
+/// See [Network::export_snapshot](struct.Network.html#method.export_snapshot) and
+/// [Network::load_snapshot](struct.Network.html#method.load_snapshot).
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::{Block, BlockHash, Hashable, Network, ValidationError};
+/// let mut network = Network::new(2);
+/// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+/// genesis.hash = genesis.hash();
+/// network.init(genesis.clone()).unwrap();
+/// let base = network.generate(2, 100, None).unwrap()[1];
+///
+/// let mut snapshot = network.export_snapshot(&base).unwrap();
+/// assert_eq!(snapshot.base.height, 3);
+/// assert_eq!(snapshot.check(), Ok(()));
+///
+/// snapshot.utxos.pop();
+/// assert_eq!(snapshot.check(), Err(ValidationError::InvalidSnapshot));
+///
+/// // an output from beyond the base block, even with a matching content hash
+/// let mut snapshot = network.export_snapshot(&base).unwrap();
+/// snapshot.utxos[0].1.height = 4;
+/// snapshot.base.content_hash = snapshot.hash();
+/// assert_eq!(snapshot.check(), Err(ValidationError::InvalidSnapshot));
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UtxoSnapshot {
+    #[serde(flatten)]
+    pub base: SnapshotBase,
+    /// The base block, which the blocks following it are validated against.
+    pub block: Block,
+    /// The unspent outputs as of the base block with their entries, in output order.
+    pub utxos: Vec<(Output, UtxoEntry)>,
+}
+
+impl Hashable for UtxoSnapshot {
+    /// Returns the [canonical encoding](trait.Encode.html) of the genesis hash, the base hash,
+    /// height and totalWork, and the unspent outputs with their entries, which would later be
+    /// hashed into the content hash.
+    fn bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.base.genesis.encode_to(&mut bytes);
+        self.base.hash.encode_to(&mut bytes);
+        self.base.height.encode_to(&mut bytes);
+        self.base.total_work.encode_to(&mut bytes);
+        (self.utxos.len() as u32).encode_to(&mut bytes);
+        for (output, entry) in &self.utxos {
+            output.encode_to(&mut bytes);
+            entry.encode_to(&mut bytes);
+        }
+        bytes
+    }
+}
+
+impl UtxoSnapshot {
+    /// Creates the snapshot of the unspent outputs as of the base block, at the given height and
+    /// totalWork, and computes its content hash.
+    pub fn new(
+        genesis: BlockHash,
+        block: Block,
+        height: u64,
+        total_work: u64,
+        utxos: &UtxoSet,
+    ) -> Self {
+        let page = utxos.page(&UtxoFilter::default(), None, usize::MAX);
+        let mut snapshot = UtxoSnapshot {
+            base: SnapshotBase {
+                genesis,
+                hash: block.hash,
+                height,
+                total_work,
+                content_hash: BlockHash::ZERO,
+            },
+            block,
+            utxos: page.utxos.into_iter().map(|(o, e)| (*o, *e)).collect(),
+        };
+        snapshot.base.content_hash = snapshot.hash();
+        snapshot
+    }
+    /// Checks that the snapshot matches its content hash and its base block, and that its outputs
+    /// are in strictly increasing output order and were created between the genesis block and the
+    /// base block.
+    ///
+    /// The proof of work of the base block is checked when the snapshot is
+    /// [loaded](struct.Network.html#method.load_snapshot).
+    pub fn check(&self) -> Result<(), ValidationError> {
+        if self.block.hash != self.base.hash
+            || self.base.height == 0
+            || self.hash() != self.base.content_hash
+            || self
+                .utxos
+                .iter()
+                .any(|(_, entry)| entry.height == 0 || entry.height > self.base.height)
+            || self.utxos.windows(2).any(|pair| pair[0].0 >= pair[1].0)
+        {
+            return Err(ValidationError::InvalidSnapshot);
+        }
+        Ok(())
+    }
+    /// Returns the unspent outputs of the snapshot.
+    pub fn utxo_set(&self) -> UtxoSet {
+        self.utxos.iter().copied().collect()
+    }
+}
@@ -0,0 +1,189 @@
+use super::{Block, Decode, Encode};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// A block read back from a [BlockStore](struct.BlockStore.html).
+pub enum StoredBlock {
+    /// A block passed to [Network::init](struct.Network.html#method.init).
+    Genesis(Block),
+    /// A block passed to [Network::submit](struct.Network.html#method.submit).
+    Block(Block),
+}
+
+/// An append-only log of the blocks accepted by a [Network](struct.Network.html).
+///
+/// Each record of the file is a `u8` kind (`0` for the genesis block, `1` for the others), the
+/// length of the block encoding as a little-endian `u32` and the
+/// [encoded block](trait.Encode.html), so that replaying the file in order rebuilds the network.
+pub struct BlockStore {
+    file: File,
+}
+
+/// An iterator over the blocks of a [BlockStore](struct.BlockStore.html), reading one record at
+/// a time so that the whole log is never held in memory.
+pub struct StoredBlocks {
+    path: PathBuf,
+    reader: BufReader<File>,
+    /// The position of the next record.
+    offset: u64,
+    /// The length of the file when it was opened.
+    len: u64,
+    done: bool,
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl BlockStore {
+    /// Opens the log at the given path, creating it if it doesn't exist.
+    ///
+    /// Returns the store along with an iterator over the blocks it already contains, in order,
+    /// which must be consumed before blocks are appended. A partial record at the end of the
+    /// file, left by a crash while a block was appended, is truncated when it is reached: that
+    /// block was never acknowledged as stored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, BlockStore, Hashable};
+    /// # use std::io::Write;
+    /// let path = std::env::temp_dir().join("mycoin-torn-store-example.dat");
+    /// # let _ = std::fs::remove_file(&path);
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+    /// genesis.hash = genesis.hash();
+    /// let (mut store, _) = BlockStore::open(&path).unwrap();
+    /// store.append(&genesis, true).unwrap();
+    ///
+    /// // a crash in the middle of the next record
+    /// let mut file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+    /// file.write_all(&[1, 200, 0]).unwrap();
+    ///
+    /// let (_, blocks) = BlockStore::open(&path).unwrap();
+    /// assert_eq!(blocks.map(Result::unwrap).count(), 1);
+    /// # std::fs::remove_file(path).unwrap();
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<(BlockStore, StoredBlocks)> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let reader = File::open(&path)?;
+        let blocks = StoredBlocks {
+            len: reader.metadata()?.len(),
+            path,
+            reader: BufReader::new(reader),
+            offset: 0,
+            done: false,
+        };
+        Ok((BlockStore { file }, blocks))
+    }
+    /// Appends an accepted block to the log, returning once the record has reached the disk so
+    /// that an acknowledged block survives a crash or a power loss.
+    pub fn append(&mut self, block: &Block, genesis: bool) -> io::Result<()> {
+        let bytes = block.encode();
+        let mut record = Vec::with_capacity(5 + bytes.len());
+        (if genesis { 0u8 } else { 1u8 }).encode_to(&mut record);
+        (bytes.len() as u32).encode_to(&mut record);
+        record.extend(bytes);
+        self.file.write_all(&record)?;
+        self.file.sync_data()
+    }
+}
+
+impl StoredBlocks {
+    /// Reads the next record, or returns `None` at the end of the log.
+    fn read_record(&mut self) -> io::Result<Option<StoredBlock>> {
+        if self.offset == self.len {
+            return Ok(None);
+        }
+        let mut header = [0u8; 5];
+        if self.len - self.offset < header.len() as u64 {
+            return self.truncate();
+        }
+        self.reader.read_exact(&mut header)?;
+        let len = u32::decode(&header[1..]).map_err(invalid_data)? as u64;
+        if self.len - self.offset - 5 < len {
+            return self.truncate();
+        }
+        let mut bytes = vec![0u8; len as usize];
+        self.reader.read_exact(&mut bytes)?;
+        self.offset += 5 + len;
+        let block = Block::decode(&bytes).map_err(invalid_data)?;
+        match header[0] {
+            0 => Ok(Some(StoredBlock::Genesis(block))),
+            1 => Ok(Some(StoredBlock::Block(block))),
+            _ => Err(invalid_data("unknown block record")),
+        }
+    }
+    /// Cuts a torn final record off the log.
+    fn truncate(&mut self) -> io::Result<Option<StoredBlock>> {
+        let file = OpenOptions::new().write(true).open(&self.path)?;
+        file.set_len(self.offset)?;
+        file.sync_data()?;
+        Ok(None)
+    }
+}
+
+impl Iterator for StoredBlocks {
+    type Item = io::Result<StoredBlock>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let record = self.read_record().transpose();
+        self.done = !matches!(record, Some(Ok(_)));
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BlockHash, Hashable};
+    use std::fs;
+
+    fn block(nonce: u64) -> Block {
+        let mut block = Block::new(0, BlockHash::ZERO, nonce, BlockHash::ZERO, vec![]);
+        block.hash = block.hash();
+        block
+    }
+
+    fn nonces(blocks: StoredBlocks) -> Vec<u64> {
+        blocks
+            .map(|stored| match stored.unwrap() {
+                StoredBlock::Genesis(block) | StoredBlock::Block(block) => block.nonce,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn torn_records_are_truncated() {
+        let path = std::env::temp_dir().join("mycoin-store-torn-records.dat");
+        let _ = fs::remove_file(&path);
+        let (mut store, _) = BlockStore::open(&path).unwrap();
+        store.append(&block(1), true).unwrap();
+        store.append(&block(2), false).unwrap();
+        let len = fs::metadata(&path).unwrap().len();
+
+        // a crash in the header, then in the body of the next record
+        for torn in [vec![1u8, 200], vec![1u8, 200, 0, 0, 0, 7, 7]] {
+            let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(&torn).unwrap();
+            let (_, blocks) = BlockStore::open(&path).unwrap();
+            assert_eq!(nonces(blocks), vec![1, 2]);
+            assert_eq!(fs::metadata(&path).unwrap().len(), len);
+        }
+
+        let (mut store, blocks) = BlockStore::open(&path).unwrap();
+        assert_eq!(nonces(blocks), vec![1, 2]);
+        store.append(&block(3), false).unwrap();
+        let (_, blocks) = BlockStore::open(&path).unwrap();
+        assert_eq!(nonces(blocks), vec![1, 2, 3]);
+        fs::remove_file(path).unwrap();
+    }
+}
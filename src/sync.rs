@@ -0,0 +1,272 @@
+use super::{Block, BlockHash, BlockHeader, Network, ValidationError};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+
+/// Maximum number of headers sent in a single `headers` message.
+pub const MAX_HEADERS: usize = 2000;
+/// Maximum number of block bodies requested from a single peer at once.
+pub const MAX_BLOCKS_IN_FLIGHT: usize = 16;
+/// Time in milliseconds after which a block body that was requested but not received is
+/// requested again, from another peer if possible.
+pub const BLOCK_REQUEST_TIMEOUT: u128 = 60_000;
+
+/// A struct that keeps track of a headers-first synchronization with the peers.
+///
+/// Headers received from peers are validated (linkage, proof of work and difficulty) before
+/// any block body is requested. The bodies of the validated headers are then downloaded in
+/// parallel from every peer that announced them, and handed back in order so that they can
+/// be submitted to the [Network](struct.Network.html). A body that doesn't arrive within
+/// [BLOCK_REQUEST_TIMEOUT](constant.BLOCK_REQUEST_TIMEOUT.html) is requested again.
+///
+/// # Examples
+///
+/// A fresh node catching up with a peer when connecting to it:
+///
+/// ```
+/// # use mycoinlib::{Block, BlockHash, Hashable, Network, Node};
+/// # use std::sync::{Arc, Mutex};
+/// # use std::{thread, time::Duration};
+/// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+/// genesis.hash = genesis.hash();
+///
+/// let mut ahead = Network::new(2);
+/// ahead.init(genesis.clone()).unwrap();
+/// let mut predecessor = genesis.hash.to_owned();
+/// for nonce in 1..=5 {
+///     let mut block = Block::new(0, BlockHash::ZERO, nonce, predecessor, vec![]);
+///     block.hash = block.hash();
+///     predecessor = block.hash.to_owned();
+///     ahead.submit(block).unwrap();
+/// }
+/// let mut fresh = Network::new(2);
+/// fresh.init(genesis).unwrap();
+///
+/// let ahead = Node::new(Arc::new(Mutex::new(ahead)));
+/// let fresh = Node::new(Arc::new(Mutex::new(fresh)));
+/// let addr = ahead.listen("127.0.0.1:0").unwrap();
+/// fresh.connect(addr).unwrap();
+///
+/// while fresh.network().lock().unwrap().state.height < 6 {
+///     thread::sleep(Duration::from_millis(10));
+/// }
+/// assert_eq!(fresh.network().lock().unwrap().state.hash, predecessor);
+/// ```
+#[derive(Default)]
+pub struct HeaderSync {
+    /// It maps the hash of every validated header whose block has not been submitted yet to the header.
+    pub headers: HashMap<BlockHash, BlockHeader>,
+    /// The hashes of the headers whose blocks have not been received yet, in chain order, keyed
+    /// by the position they were queued at.
+    pub queue: BTreeMap<u64, BlockHash>,
+    /// The position of the next queued header.
+    queued: u64,
+    /// It maps the hash of every queued header to its position in the queue and the peers which
+    /// announced it.
+    sources: HashMap<BlockHash, (u64, HashSet<SocketAddr>)>,
+    /// It maps the hash of every requested block to the peer it was requested from and the time
+    /// of the request.
+    pub in_flight: HashMap<BlockHash, (SocketAddr, u128)>,
+    /// Received blocks waiting for their predecessor to be submitted, keyed by predecessor hash.
+    orphans: HashMap<BlockHash, Vec<Block>>,
+}
+
+impl HeaderSync {
+    /// Creates a new `HeaderSync` instance.
+    pub fn new() -> Self {
+        HeaderSync::default()
+    }
+    /// Returns the difficulty of a block known either as a validated header or to the network.
+    fn difficulty_of(&self, network: &Network, hash: &BlockHash) -> Option<u32> {
+        match self.headers.get(hash) {
+            Some(header) => Some(header.difficulty),
+            None => network
+                .indexed_block(hash)
+                .ok()
+                .map(|indexed| indexed.header.difficulty),
+        }
+    }
+    /// Validates a batch of headers received from a peer and queues their blocks for download.
+    ///
+    /// Every header must extend a known block or header, carry a valid proof of work and must
+    /// not decrease the difficulty of its predecessor. Headers that are already known are skipped.
+    ///
+    /// Returns the number of new headers, or the reason the first invalid header was rejected.
+    pub fn add_headers(
+        &mut self,
+        network: &Network,
+        peer: SocketAddr,
+        headers: Vec<BlockHeader>,
+    ) -> Result<usize, ValidationError> {
+        let mut added = 0;
+        for header in headers {
+            if network.contains_block(&header.hash) {
+                continue;
+            }
+            if let Some((_, sources)) = self.sources.get_mut(&header.hash) {
+                sources.insert(peer);
+                continue;
+            }
+            if self.headers.contains_key(&header.hash) {
+                continue;
+            }
+            let predecessor_difficulty = match self.difficulty_of(network, &header.predecessor) {
+                Some(difficulty) => difficulty,
+                None => return Err(ValidationError::NoPredecessor),
+            };
+            header.validate_with(&*network.pow)?;
+            if predecessor_difficulty > header.difficulty {
+                return Err(ValidationError::DifficultyDecreased);
+            }
+
+            let mut sources = HashSet::new();
+            sources.insert(peer);
+            self.sources
+                .insert(header.hash.to_owned(), (self.queued, sources));
+            self.queue.insert(self.queued, header.hash.to_owned());
+            self.queued += 1;
+            self.headers.insert(header.hash.to_owned(), header);
+            added += 1;
+        }
+        Ok(added)
+    }
+    /// Returns `true` if the block belongs to a validated header waiting for its body.
+    pub fn is_expected(&self, hash: &BlockHash) -> bool {
+        self.headers.contains_key(hash)
+    }
+    /// Assigns queued downloads to the given peers at the given time, at most
+    /// [MAX_BLOCKS_IN_FLIGHT](constant.MAX_BLOCKS_IN_FLIGHT.html) per peer.
+    ///
+    /// Requests older than [BLOCK_REQUEST_TIMEOUT](constant.BLOCK_REQUEST_TIMEOUT.html) don't
+    /// count towards the limit and are assigned again, preferably to another peer.
+    ///
+    /// Returns the hashes to request from each peer.
+    pub fn schedule(
+        &mut self,
+        peers: &[SocketAddr],
+        now: u128,
+    ) -> Vec<(SocketAddr, Vec<BlockHash>)> {
+        let pending = |requested_at: u128| now.saturating_sub(requested_at) < BLOCK_REQUEST_TIMEOUT;
+        let mut load: HashMap<SocketAddr, usize> = peers.iter().map(|p| (*p, 0)).collect();
+        for (peer, requested_at) in self.in_flight.values() {
+            if let Some(count) = load.get_mut(peer).filter(|_| pending(*requested_at)) {
+                *count += 1;
+            }
+        }
+
+        let mut requests: HashMap<SocketAddr, Vec<BlockHash>> = HashMap::new();
+        for hash in self.queue.values() {
+            let stalled = match self.in_flight.get(hash) {
+                Some((_, requested_at)) if pending(*requested_at) => continue,
+                Some((peer, _)) => Some(*peer),
+                None => None,
+            };
+            let (_, sources) = &self.sources[hash];
+            let peer = peers
+                .iter()
+                .filter(|p| sources.contains(p) && load[p] < MAX_BLOCKS_IN_FLIGHT)
+                .min_by_key(|p| (Some(**p) == stalled, load[p]));
+            if let Some(peer) = peer {
+                *load.get_mut(peer).unwrap() += 1;
+                self.in_flight.insert(hash.to_owned(), (*peer, now));
+                requests.entry(*peer).or_default().push(hash.to_owned());
+            }
+        }
+        requests.into_iter().collect()
+    }
+    /// Accepts the body of a validated header.
+    ///
+    /// Returns the blocks that are ready to be submitted, in order: the received block and its
+    /// already received descendants, once its predecessor is known to the network.
+    pub fn block_received(&mut self, network: &Network, block: Block) -> Vec<Block> {
+        let header = match self.headers.get(&block.hash) {
+            Some(header) => header,
+            None => return vec![],
+        };
+        if *header != block.header() {
+            // the body doesn't match the announced header, download it again
+            self.in_flight.remove(&block.hash);
+            return vec![];
+        }
+        self.in_flight.remove(&block.hash);
+        if let Some((position, _)) = self.sources.remove(&block.hash) {
+            self.queue.remove(&position);
+        }
+
+        if !network.contains_block(&block.predecessor) {
+            self.orphans
+                .entry(block.predecessor.to_owned())
+                .or_default()
+                .push(block);
+            return vec![];
+        }
+
+        let mut ready = vec![];
+        let mut pending = VecDeque::new();
+        pending.push_back(block);
+        while let Some(block) = pending.pop_front() {
+            self.headers.remove(&block.hash);
+            if let Some(children) = self.orphans.remove(&block.hash) {
+                pending.extend(children);
+            }
+            ready.push(block);
+        }
+        ready
+    }
+    /// Requeues the blocks requested from a disconnected peer and forgets it as a source.
+    pub fn peer_disconnected(&mut self, peer: SocketAddr) {
+        self.in_flight.retain(|_, (p, _)| *p != peer);
+        for (_, sources) in self.sources.values_mut() {
+            sources.remove(&peer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Hashable;
+
+    /// Returns a network with a genesis block, and the headers of `count` blocks mined on
+    /// another network with the same genesis block, along with the blocks.
+    fn chain(count: u64) -> (Network, Vec<Block>) {
+        let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![]);
+        genesis.hash = genesis.hash();
+        let mut network = Network::new(2);
+        network.init(genesis.clone()).unwrap();
+        let mut ahead = Network::new(2);
+        ahead.init(genesis).unwrap();
+        let hashes = ahead.generate(count, 100, None).unwrap();
+        let blocks = hashes.iter().map(|h| ahead.get_block(h).unwrap()).collect();
+        (network, blocks)
+    }
+
+    #[test]
+    fn stalled_requests_are_sent_again_to_another_peer() {
+        let (network, blocks) = chain(2);
+        let (slow, fast): (SocketAddr, SocketAddr) = (
+            "127.0.0.1:1".parse().unwrap(),
+            "127.0.0.1:2".parse().unwrap(),
+        );
+        let mut sync = HeaderSync::new();
+        let headers: Vec<BlockHeader> = blocks.iter().map(Block::header).collect();
+        sync.add_headers(&network, slow, headers.clone()).unwrap();
+        assert_eq!(
+            sync.schedule(&[slow, fast], 0),
+            [(slow, vec![headers[0].hash, headers[1].hash])]
+        );
+        sync.add_headers(&network, fast, headers.clone()).unwrap();
+
+        assert!(sync
+            .schedule(&[slow, fast], BLOCK_REQUEST_TIMEOUT - 1)
+            .is_empty());
+        let requests = sync.schedule(&[slow, fast], BLOCK_REQUEST_TIMEOUT);
+        assert_eq!(requests, [(fast, vec![headers[0].hash, headers[1].hash])]);
+
+        let ready = sync.block_received(&network, blocks[1].clone());
+        assert!(ready.is_empty());
+        assert_eq!(sync.queue.values().collect::<Vec<_>>(), [&headers[0].hash]);
+        let ready = sync.block_received(&network, blocks[0].clone());
+        assert_eq!(ready, blocks);
+        assert!(sync.queue.is_empty() && sync.in_flight.is_empty() && sync.headers.is_empty());
+    }
+}
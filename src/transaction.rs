@@ -1,8 +1,12 @@
+use super::utxo::{node_hash, tree_levels};
+use super::{Block, BlockHash, BlockHeader, Encode, PowHasher, Sha256, ValidationError};
 use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
+use std::convert::TryFrom;
 
 /// A struct that represent an input or output of a [transaction](struct.Transaction.html).
-#[derive(Hash, Eq, PartialEq, Debug, Copy, Clone, Serialize, Deserialize)]
+/// Outputs are ordered by id, then by amount.
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct Output {
     pub id: u64,
     pub amount: u64,
@@ -10,18 +14,28 @@ pub struct Output {
 
 /// A transaction contains any number of inputs and any number of outputs, which
 /// must sum to the same amount.
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Transaction {
     pub inputs: Vec<Output>,
     pub outputs: Vec<Output>,
 }
 
 impl Transaction {
-    pub fn input_value(&self) -> u64 {
-        self.inputs.iter().map(|input| input.amount).sum()
+    /// Returns the sum of the amounts of the inputs, or `None` if it overflows.
+    pub fn checked_input_value(&self) -> Option<u64> {
+        self.inputs
+            .iter()
+            .try_fold(0u64, |sum, input| sum.checked_add(input.amount))
     }
-    pub fn output_value(&self) -> u64 {
-        self.outputs.iter().map(|output| output.amount).sum()
+    /// Returns the sum of the amounts of the outputs, or `None` if it overflows.
+    pub fn checked_output_value(&self) -> Option<u64> {
+        self.outputs
+            .iter()
+            .try_fold(0u64, |sum, output| sum.checked_add(output.amount))
+    }
+    /// Returns `true` if the transaction lists the same input more than once.
+    pub fn has_duplicate_inputs(&self) -> bool {
+        self.inputs().len() != self.inputs.len()
     }
     pub fn inputs(&self) -> HashSet<Output> {
         let mut hn = HashSet::new();
@@ -44,6 +58,142 @@ impl Transaction {
         hn
     }
     pub fn is_coinbase(&self) -> bool {
-        self.inputs.len() == 0
+        self.inputs.is_empty()
+    }
+}
+
+/// Returns the hash of the leaf of a transaction in the tree of the
+/// [transactions root](struct.Block.html#method.transactions_root).
+fn leaf_hash(transaction: &Transaction) -> BlockHash {
+    let mut bytes = vec![0];
+    transaction.encode_to(&mut bytes);
+    BlockHash(Sha256.digest(&bytes))
+}
+
+/// Returns the root committing to both the number of transactions and the top of their tree,
+/// which is missing if there is no transaction.
+fn counted_root(count: u32, top: Option<&BlockHash>) -> BlockHash {
+    let mut bytes = count.encode();
+    if let Some(top) = top {
+        top.encode_to(&mut bytes);
+    }
+    BlockHash(Sha256.digest(&bytes))
+}
+
+/// Returns the levels of the tree over the transactions, from the leaves up to the top.
+fn transaction_levels(transactions: &[Transaction]) -> Vec<Vec<BlockHash>> {
+    tree_levels(transactions.iter().map(leaf_hash).collect())
+}
+
+/// Returns the root of the Merkle tree over the transactions, see
+/// [Block::transactions_root](struct.Block.html#method.transactions_root).
+pub fn transactions_root(transactions: &[Transaction]) -> BlockHash {
+    let levels = transaction_levels(transactions);
+    counted_root(
+        transactions.len() as u32,
+        levels.last().and_then(|top| top.first()),
+    )
+}
+
+/// A struct that proves a transaction is in a block, to verify against the
+/// [header](struct.BlockHeader.html) of the block alone, e.g. by a
+/// [light client](struct.LightClient.html).
+///
+/// The proof holds the position of the transaction, the number of transactions of the block
+/// and the branch of the [transactions root](struct.Block.html#method.transactions_root) tree:
+/// the sibling of each node on the path from the leaf of the transaction up to the top, so
+/// its size grows with the logarithm of the number of transactions.
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::{Block, BlockHash, Output, Transaction, TransactionProof};
+/// let transactions: Vec<Transaction> = (0..5)
+///     .map(|id| Transaction {
+///         inputs: vec![],
+///         outputs: vec![Output { id, amount: 50 }],
+///     })
+///     .collect();
+/// let block = Block::new(1, BlockHash::ZERO, 0, BlockHash::ZERO, transactions.clone());
+/// let header = block.header();
+///
+/// let proof = TransactionProof::new(&block, 4).unwrap();
+/// assert_eq!(proof.branch.len(), 1);
+/// assert!(proof.verify(&transactions[4], &header).is_ok());
+/// assert!(proof.verify(&transactions[3], &header).is_err());
+///
+/// let mut moved = TransactionProof::new(&block, 2).unwrap();
+/// assert!(moved.verify(&transactions[2], &header).is_ok());
+/// moved.index = 3;
+/// assert!(moved.verify(&transactions[2], &header).is_err());
+/// ```
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TransactionProof {
+    /// The hash of the block including the transaction.
+    pub block: BlockHash,
+    /// The position of the transaction in the block.
+    pub index: usize,
+    /// The number of transactions of the block.
+    pub count: usize,
+    /// The siblings of the nodes on the path from the transaction up to the top of the tree,
+    /// skipping the levels where the node has no sibling.
+    pub branch: Vec<BlockHash>,
+}
+
+impl TransactionProof {
+    /// Creates the proof that the transaction at the given position is in the block.
+    ///
+    /// Returns `None` if the block has no transaction at this position.
+    pub fn new(block: &Block, index: usize) -> Option<Self> {
+        if index >= block.transactions.len() {
+            return None;
+        }
+        let levels = transaction_levels(&block.transactions);
+        let mut position = index;
+        let mut branch = vec![];
+        for level in &levels[..levels.len() - 1] {
+            if let Some(sibling) = level.get(position ^ 1) {
+                branch.push(*sibling);
+            }
+            position /= 2;
+        }
+        Some(TransactionProof {
+            block: block.hash,
+            index,
+            count: block.transactions.len(),
+            branch,
+        })
+    }
+    /// Verifies that the transaction is in the block of the given header.
+    ///
+    /// Returns an error if the proof is not about this header, or if the transaction and the
+    /// branch don't hash up to the transactions root of the header.
+    pub fn verify(
+        &self,
+        transaction: &Transaction,
+        header: &BlockHeader,
+    ) -> Result<(), ValidationError> {
+        let count = u32::try_from(self.count).map_err(|_| ValidationError::InvalidProof)?;
+        if self.block != header.hash || self.index >= self.count {
+            return Err(ValidationError::InvalidProof);
+        }
+        let mut branch = self.branch.iter();
+        let mut hash = leaf_hash(transaction);
+        let (mut position, mut width) = (self.index, self.count);
+        while width > 1 {
+            if position % 2 == 1 {
+                let left = branch.next().ok_or(ValidationError::InvalidProof)?;
+                hash = node_hash(left, &hash);
+            } else if position + 1 < width {
+                let right = branch.next().ok_or(ValidationError::InvalidProof)?;
+                hash = node_hash(&hash, right);
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        if branch.next().is_some() || counted_root(count, Some(&hash)) != header.transactions_root {
+            return Err(ValidationError::InvalidProof);
+        }
+        Ok(())
     }
 }
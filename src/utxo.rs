@@ -0,0 +1,706 @@
+use super::{Block, BlockHash, Encode, Output, PowHasher, Sha256, ValidationError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::iter::FromIterator;
+use std::ops::Bound;
+
+/// The number of unspent outputs on a [page](struct.UtxoSet.html#method.page) when the query
+/// doesn't say.
+pub const UTXO_PAGE_SIZE: usize = 100;
+
+/// A struct that holds what is known about an unspent output besides its id and amount.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UtxoEntry {
+    /// The height of the block that created the output, the genesis block being at height 1.
+    pub height: u64,
+    /// Whether the output was created by a coinbase transaction.
+    pub coinbase: bool,
+}
+
+/// A struct that selects unspent outputs by ranges, all bounds included, of their id,
+/// amount and creation height. Missing bounds don't restrict the selection.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UtxoFilter {
+    pub min_id: Option<u64>,
+    pub max_id: Option<u64>,
+    pub min_amount: Option<u64>,
+    pub max_amount: Option<u64>,
+    pub min_height: Option<u64>,
+    pub max_height: Option<u64>,
+}
+
+impl UtxoFilter {
+    /// Returns `true` if the unspent output is selected by the filter.
+    pub fn matches(&self, output: &Output, entry: &UtxoEntry) -> bool {
+        let within = |value: u64, min: Option<u64>, max: Option<u64>| {
+            min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+        };
+        within(output.id, self.min_id, self.max_id)
+            && within(output.amount, self.min_amount, self.max_amount)
+            && within(entry.height, self.min_height, self.max_height)
+    }
+}
+
+/// A page of the unspent outputs selected by a [filter](struct.UtxoFilter.html), see
+/// [UtxoSet::page](struct.UtxoSet.html#method.page).
+#[derive(Clone, Debug, PartialEq)]
+pub struct UtxoPage<'a> {
+    /// The outputs of the page with their entries, ordered by output.
+    pub utxos: Vec<(&'a Output, &'a UtxoEntry)>,
+    /// The number of outputs selected by the filter, on every page.
+    pub count: usize,
+    /// The sum of the amounts of the outputs selected by the filter, on every page.
+    pub total: u64,
+    /// The cursor of the next page, if there are more outputs.
+    pub next: Option<Output>,
+}
+
+/// A leaf of the tree over a [UTXO set](struct.UtxoSet.html#method.root) with the hashes needed
+/// to compute the root from it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UtxoLeaf {
+    pub output: Output,
+    pub entry: UtxoEntry,
+    /// The position of the leaf, in output order.
+    pub index: u64,
+    /// The hashes of the siblings of the leaf and of its ancestors, from the bottom up. Nodes
+    /// without a sibling have no hash in the path.
+    pub path: Vec<BlockHash>,
+}
+
+impl UtxoLeaf {
+    /// Returns the root of a tree of `count` leaves computed from the leaf and its path, if the
+    /// path has the length the position of the leaf requires.
+    fn root(&self, count: u64) -> Option<BlockHash> {
+        if self.index >= count {
+            return None;
+        }
+        let mut hash = leaf_hash(&self.output, &self.entry);
+        let mut path = self.path.iter();
+        let (mut index, mut size) = (self.index, count);
+        while size > 1 {
+            if index ^ 1 < size {
+                let sibling = path.next()?;
+                hash = match index % 2 {
+                    0 => node_hash(&hash, sibling),
+                    _ => node_hash(sibling, &hash),
+                };
+            }
+            index /= 2;
+            size = size.div_ceil(2);
+        }
+        match path.next() {
+            Some(_) => None,
+            None => Some(counted_root(count, &hash)),
+        }
+    }
+}
+
+/// A proof that an output is, or is not, unspent in a [UTXO set](struct.UtxoSet.html) with a
+/// given [root](struct.UtxoSet.html#method.root).
+///
+/// An unspent output is proved by its own leaf. Any other output is proved not to be unspent by
+/// the adjacent leaves it would be between, or by the first or last leaf alone.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UtxoProof {
+    /// The number of unspent outputs in the set.
+    pub count: u64,
+    pub leaves: Vec<UtxoLeaf>,
+}
+
+impl UtxoProof {
+    /// Verifies the proof for the output against the root of a UTXO set.
+    ///
+    /// Returns the entry of the output if it is unspent, `None` if it is not, or an error if
+    /// the proof doesn't match the root or doesn't prove anything about the output.
+    pub fn verify(
+        &self,
+        output: &Output,
+        root: &BlockHash,
+    ) -> Result<Option<UtxoEntry>, ValidationError> {
+        if self
+            .leaves
+            .iter()
+            .any(|leaf| leaf.root(self.count).as_ref() != Some(root))
+        {
+            return Err(ValidationError::InvalidProof);
+        }
+        match self.leaves.as_slice() {
+            [] if self.count == 0 && counted_root(0, &BlockHash::ZERO) == *root => Ok(None),
+            [leaf] if leaf.output == *output => Ok(Some(leaf.entry)),
+            [after] if after.index == 0 && *output < after.output => Ok(None),
+            [before] if before.index + 1 == self.count && before.output < *output => Ok(None),
+            [before, after]
+                if before.index + 1 == after.index
+                    && before.output < *output
+                    && *output < after.output =>
+            {
+                Ok(None)
+            }
+            _ => Err(ValidationError::InvalidProof),
+        }
+    }
+}
+
+/// Returns the hash of the leaf of an unspent output.
+fn leaf_hash(output: &Output, entry: &UtxoEntry) -> BlockHash {
+    let mut bytes = vec![0];
+    output.encode_to(&mut bytes);
+    entry.encode_to(&mut bytes);
+    BlockHash(Sha256.digest(&bytes))
+}
+
+/// Returns the hash of an inner node of the tree.
+pub fn node_hash(left: &BlockHash, right: &BlockHash) -> BlockHash {
+    let mut bytes = vec![1];
+    left.encode_to(&mut bytes);
+    right.encode_to(&mut bytes);
+    BlockHash(Sha256.digest(&bytes))
+}
+
+/// Returns the root committing to both the number of leaves and the top of the tree.
+fn counted_root(count: u64, top: &BlockHash) -> BlockHash {
+    let mut bytes = vec![2];
+    count.encode_to(&mut bytes);
+    top.encode_to(&mut bytes);
+    BlockHash(Sha256.digest(&bytes))
+}
+
+/// Returns the levels of the tree over the leaf hashes, from the leaves up to the top. A node
+/// without a sibling moves up a level unchanged.
+pub fn tree_levels(leaves: Vec<BlockHash>) -> Vec<Vec<BlockHash>> {
+    let mut levels = vec![leaves];
+    while let Some(level) = levels.last().filter(|level| level.len() > 1) {
+        let next = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                _ => pair[0],
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+/// The changes a block made to a [UTXO set](struct.UtxoSet.html), which can be
+/// [undone](struct.UtxoSet.html#method.undo) to get the set back as it was before the block.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UtxoUndo {
+    /// The outputs spent by the block, with their entries.
+    pub spent: Vec<(Output, UtxoEntry)>,
+    /// The outputs created by the block, with their entries and the entries they replaced, if
+    /// any.
+    pub created: Vec<(Output, UtxoEntry, Option<UtxoEntry>)>,
+}
+
+/// The net changes between the [UTXO sets](struct.UtxoSet.html) of two blocks, built by
+/// undoing the blocks from the first one back to their common ancestor and then redoing the
+/// blocks up to the second one.
+///
+/// Outputs created and spent again on the way don't appear in the diff.
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::{Block, BlockHash, Output, Transaction, UtxoDiff, UtxoSet};
+/// let coin = Output { id: 1, amount: 50 };
+/// let change = Output { id: 2, amount: 50 };
+/// let block = |inputs, outputs| {
+///     let transaction = Transaction { inputs, outputs };
+///     Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![transaction])
+/// };
+///
+/// let mut utxos = UtxoSet::new();
+/// utxos.apply(&block(vec![], vec![coin]), 1);
+/// let before = utxos.clone();
+/// let mut diff = UtxoDiff::default();
+/// diff.redo(&utxos.apply(&block(vec![coin], vec![change]), 2));
+/// assert_eq!(diff.spent(), vec![(&coin, before.get(&coin).unwrap())]);
+/// assert_eq!(diff.created(), vec![(&change, utxos.get(&change).unwrap())]);
+///
+/// let mut after = before.clone();
+/// after.apply_diff(&diff);
+/// assert_eq!(after, utxos);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UtxoDiff {
+    /// The entries of every output touched, in the first set and in the second set.
+    changes: HashMap<Output, (Option<UtxoEntry>, Option<UtxoEntry>)>,
+}
+
+impl UtxoDiff {
+    /// Records that the output goes from the `from` entry to the `to` entry, the first time it
+    /// is touched giving its entry in the first set.
+    fn change(&mut self, output: Output, from: Option<UtxoEntry>, to: Option<UtxoEntry>) {
+        self.changes.entry(output).or_insert((from, from)).1 = to;
+    }
+    /// Adds the changes of undoing a block, the last one applied so far.
+    pub fn undo(&mut self, undo: &UtxoUndo) {
+        for (output, entry, replaced) in undo.created.iter().rev() {
+            self.change(*output, Some(*entry), *replaced);
+        }
+        for (output, entry) in &undo.spent {
+            self.change(*output, None, Some(*entry));
+        }
+    }
+    /// Adds the changes of applying a block again, given the changes it made.
+    pub fn redo(&mut self, undo: &UtxoUndo) {
+        for (output, entry) in &undo.spent {
+            self.change(*output, Some(*entry), None);
+        }
+        for (output, entry, replaced) in &undo.created {
+            self.change(*output, *replaced, Some(*entry));
+        }
+    }
+    /// Returns the outputs with their entries, in output order, that are unspent in the second
+    /// set but not, or with another entry, in the first one.
+    pub fn created(&self) -> Vec<(&Output, &UtxoEntry)> {
+        self.entries(|(from, to)| if from != to { to.as_ref() } else { None })
+    }
+    /// Returns the outputs with their entries, in output order, that are unspent in the first
+    /// set but not, or with another entry, in the second one.
+    pub fn spent(&self) -> Vec<(&Output, &UtxoEntry)> {
+        self.entries(|(from, to)| if from != to { from.as_ref() } else { None })
+    }
+    fn entries<'a, F>(&'a self, entry: F) -> Vec<(&'a Output, &'a UtxoEntry)>
+    where
+        F: Fn(&'a (Option<UtxoEntry>, Option<UtxoEntry>)) -> Option<&'a UtxoEntry>,
+    {
+        let mut entries: Vec<_> = self
+            .changes
+            .iter()
+            .filter_map(|(output, change)| entry(change).map(|entry| (output, entry)))
+            .collect();
+        entries.sort_unstable_by_key(|(output, _)| **output);
+        entries
+    }
+}
+
+/// A struct that keeps track of the unspent transaction outputs of a chain.
+///
+/// Outputs are keyed by their identity (id and amount) and kept in output order along with the
+/// hashes of their leaves in the tree of the [root](#method.root), so the root is computed
+/// without sorting or hashing the outputs again. The set is updated by applying the changes of
+/// each block instead of being rebuilt.
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::{Block, BlockHash, Output, Transaction, UtxoSet};
+/// let coin = Output { id: 1, amount: 50 };
+/// let coinbase = Transaction {
+///     inputs: vec![],
+///     outputs: vec![coin],
+/// };
+/// let spend = Transaction {
+///     inputs: vec![coin],
+///     outputs: vec![Output { id: 2, amount: 50 }],
+/// };
+/// let block = |transactions| Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, transactions);
+///
+/// let mut utxos = UtxoSet::new();
+/// utxos.apply(&block(vec![coinbase]), 1);
+/// assert!(utxos.get(&coin).unwrap().coinbase);
+///
+/// let before = utxos.clone();
+/// let undo = utxos.apply(&block(vec![spend]), 2);
+/// assert!(!utxos.contains(&coin));
+/// assert_eq!(utxos.len(), 1);
+///
+/// utxos.undo(&undo);
+/// assert_eq!(utxos, before);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UtxoSet {
+    /// The unspent outputs in output order, with their entries and leaf hashes.
+    entries: BTreeMap<Output, (UtxoEntry, BlockHash)>,
+    /// The sum of the amounts of the unspent outputs.
+    amount: u128,
+}
+
+impl FromIterator<(Output, UtxoEntry)> for UtxoSet {
+    fn from_iter<I: IntoIterator<Item = (Output, UtxoEntry)>>(iter: I) -> Self {
+        let mut utxos = UtxoSet::new();
+        for (output, entry) in iter {
+            utxos.insert(output, entry);
+        }
+        utxos
+    }
+}
+
+impl UtxoSet {
+    /// Creates an empty `UtxoSet`.
+    pub fn new() -> Self {
+        UtxoSet {
+            entries: BTreeMap::new(),
+            amount: 0,
+        }
+    }
+    /// Adds the output with its entry, returning the entry it replaced, if any.
+    fn insert(&mut self, output: Output, entry: UtxoEntry) -> Option<UtxoEntry> {
+        let leaf = leaf_hash(&output, &entry);
+        let replaced = self.entries.insert(output, (entry, leaf));
+        if replaced.is_none() {
+            self.amount += u128::from(output.amount);
+        }
+        replaced.map(|(replaced, _)| replaced)
+    }
+    /// Removes the output, returning its entry if it was unspent.
+    fn remove(&mut self, output: &Output) -> Option<UtxoEntry> {
+        let (entry, _) = self.entries.remove(output)?;
+        self.amount -= u128::from(output.amount);
+        Some(entry)
+    }
+    /// Returns the number of unspent outputs.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    /// Returns `true` if there are no unspent outputs.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Returns `true` if the output is unspent.
+    pub fn contains(&self, output: &Output) -> bool {
+        self.entries.contains_key(output)
+    }
+    /// Returns the entry of the output, if it is unspent.
+    pub fn get(&self, output: &Output) -> Option<&UtxoEntry> {
+        self.entries.get(output).map(|(entry, _)| entry)
+    }
+    /// Returns an iterator over the unspent outputs and their entries, in output order.
+    pub fn iter(&self) -> impl Iterator<Item = (&Output, &UtxoEntry)> {
+        self.entries
+            .iter()
+            .map(|(output, (entry, _))| (output, entry))
+    }
+    /// Returns an iterator over the unspent outputs, in output order.
+    pub fn outputs(&self) -> impl Iterator<Item = &Output> {
+        self.entries.keys()
+    }
+    /// Returns up to `limit` of the outputs selected by the filter that come after the `after`
+    /// cursor, in [output order](struct.Output.html), along with totals over every selected
+    /// output.
+    ///
+    /// The first page has no cursor, and each page gives the cursor of the next one. A `limit` of
+    /// `0` gives no outputs and no cursor, only the totals.
+    ///
+    /// A page starts at its cursor, or at the `min_id` of the filter, and only the totals of a
+    /// filter on amounts or heights scan every output.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Output, Transaction, UtxoFilter, UtxoSet};
+    /// let outputs = (1..=5).map(|id| Output { id, amount: id * 10 }).collect();
+    /// let coinbase = Transaction {
+    ///     inputs: vec![],
+    ///     outputs,
+    /// };
+    /// let mut utxos = UtxoSet::new();
+    /// utxos.apply(&Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![coinbase]), 1);
+    ///
+    /// let filter = UtxoFilter {
+    ///     min_amount: Some(20),
+    ///     ..UtxoFilter::default()
+    /// };
+    /// let first = utxos.page(&filter, None, 2);
+    /// assert_eq!((first.count, first.total), (4, 140));
+    /// assert_eq!(first.utxos[0].0.id, 2);
+    ///
+    /// let second = utxos.page(&filter, first.next, 2);
+    /// assert_eq!(second.utxos[1].0.id, 5);
+    /// assert_eq!(second.next, None);
+    /// ```
+    pub fn page(&self, filter: &UtxoFilter, after: Option<Output>, limit: usize) -> UtxoPage<'_> {
+        let first = Output {
+            id: filter.min_id.unwrap_or(0),
+            amount: 0,
+        };
+        let (count, total) = if *filter == UtxoFilter::default() {
+            (self.len(), self.amount.min(u128::from(u64::MAX)) as u64)
+        } else {
+            self.selected(filter, Bound::Included(first))
+                .fold((0, 0u64), |(count, total), (output, _)| {
+                    (count + 1, total.saturating_add(output.amount))
+                })
+        };
+        let start = match after {
+            Some(after) if after >= first => Bound::Excluded(after),
+            _ => Bound::Included(first),
+        };
+        let mut selected = self.selected(filter, start);
+        let utxos: Vec<_> = selected.by_ref().take(limit).collect();
+        let next = match utxos.last() {
+            Some((output, _)) if selected.next().is_some() => Some(**output),
+            _ => None,
+        };
+        UtxoPage {
+            utxos,
+            count,
+            total,
+            next,
+        }
+    }
+    /// Returns the outputs selected by the filter from the `start` bound on, in output order,
+    /// only going through the outputs within the id range of the filter.
+    fn selected<'a: 'f, 'f>(
+        &'a self,
+        filter: &'f UtxoFilter,
+        start: Bound<Output>,
+    ) -> impl Iterator<Item = (&'a Output, &'a UtxoEntry)> + 'f {
+        let last = Output {
+            id: filter.max_id.unwrap_or(u64::MAX),
+            amount: u64::MAX,
+        };
+        let range = match start {
+            Bound::Included(output) | Bound::Excluded(output) if output > last => None,
+            _ => Some(self.entries.range((start, Bound::Included(last)))),
+        };
+        range
+            .into_iter()
+            .flatten()
+            .map(|(output, (entry, _))| (output, entry))
+            .filter(move |(output, entry)| filter.matches(output, entry))
+    }
+    /// Returns the root of a Merkle tree over the unspent outputs, which blocks
+    /// [commit](struct.Block.html#structfield.utxo_root) to on some chains.
+    ///
+    /// The leaves are the SHA256 hashes of a `0` byte followed by the
+    /// [encoded](trait.Encode.html) output and entry, in output order. Each inner node is the
+    /// hash of a `1` byte followed by its two children, a node without a sibling moving up
+    /// unchanged. The root is the hash of a `2` byte followed by the number of leaves and the top
+    /// of the tree, zero for an empty set.
+    ///
+    /// The leaves are kept as outputs are added, so only the inner nodes are hashed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Output, Transaction, UtxoSet};
+    /// let outputs = (1..=5).map(|id| Output { id: id * 10, amount: 1 }).collect();
+    /// let coinbase = Transaction {
+    ///     inputs: vec![],
+    ///     outputs,
+    /// };
+    /// let mut utxos = UtxoSet::new();
+    /// utxos.apply(&Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![coinbase]), 1);
+    /// let root = utxos.root();
+    ///
+    /// let unspent = Output { id: 30, amount: 1 };
+    /// let proof = utxos.prove(&unspent);
+    /// assert_eq!(proof.verify(&unspent, &root), Ok(utxos.get(&unspent).copied()));
+    ///
+    /// let unknown = Output { id: 35, amount: 1 };
+    /// let proof = utxos.prove(&unknown);
+    /// assert_eq!(proof.leaves.len(), 2);
+    /// assert_eq!(proof.verify(&unknown, &root), Ok(None));
+    /// assert!(proof.verify(&unspent, &root).is_err());
+    /// ```
+    pub fn root(&self) -> BlockHash {
+        let leaves = self.entries.values().map(|(_, leaf)| *leaf);
+        let levels = tree_levels(leaves.collect());
+        let top = levels.last().and_then(|level| level.first());
+        counted_root(self.len() as u64, top.unwrap_or(&BlockHash::ZERO))
+    }
+    /// Returns a [proof](struct.UtxoProof.html) that the output is, or is not, unspent, to verify
+    /// against the [root](#method.root) of the set.
+    pub fn prove(&self, output: &Output) -> UtxoProof {
+        let utxos: Vec<_> = self.iter().collect();
+        let levels = tree_levels(self.entries.values().map(|(_, leaf)| *leaf).collect());
+        let leaf = |index: usize| {
+            let mut path = vec![];
+            let mut position = index;
+            for level in &levels[..levels.len() - 1] {
+                if let Some(sibling) = level.get(position ^ 1) {
+                    path.push(*sibling);
+                }
+                position /= 2;
+            }
+            UtxoLeaf {
+                output: *utxos[index].0,
+                entry: *utxos[index].1,
+                index: index as u64,
+                path,
+            }
+        };
+        let leaves = match utxos.binary_search_by_key(output, |(o, _)| **o) {
+            Ok(index) => vec![leaf(index)],
+            Err(index) => {
+                let mut leaves = vec![];
+                if index > 0 {
+                    leaves.push(leaf(index - 1));
+                }
+                if index < utxos.len() {
+                    leaves.push(leaf(index));
+                }
+                leaves
+            }
+        };
+        UtxoProof {
+            count: utxos.len() as u64,
+            leaves,
+        }
+    }
+    /// Applies the block at the given height: removes the outputs its transactions spend and
+    /// adds the outputs they create.
+    ///
+    /// The block is not validated. Returns the changes made, to [undo](#method.undo) them.
+    pub fn apply(&mut self, block: &Block, height: u64) -> UtxoUndo {
+        let mut undo = UtxoUndo::default();
+        for transaction in &block.transactions {
+            for input in &transaction.inputs {
+                if let Some(entry) = self.remove(input) {
+                    undo.spent.push((*input, entry));
+                }
+            }
+        }
+        for transaction in &block.transactions {
+            let entry = UtxoEntry {
+                height,
+                coinbase: transaction.is_coinbase(),
+            };
+            for output in &transaction.outputs {
+                let replaced = self.insert(*output, entry);
+                undo.created.push((*output, entry, replaced));
+            }
+        }
+        undo
+    }
+    /// Undoes the changes made by [applying](#method.apply) a block, which must be the last
+    /// block applied.
+    pub fn undo(&mut self, undo: &UtxoUndo) {
+        for (output, _, replaced) in undo.created.iter().rev() {
+            match replaced {
+                Some(entry) => self.insert(*output, *entry),
+                None => self.remove(output),
+            };
+        }
+        for (output, entry) in &undo.spent {
+            self.insert(*output, *entry);
+        }
+    }
+    /// Applies again the changes of a block that were [undone](#method.undo), the set being as
+    /// it was before the block.
+    pub fn redo(&mut self, undo: &UtxoUndo) {
+        for (output, _) in &undo.spent {
+            self.remove(output);
+        }
+        for (output, entry, _) in &undo.created {
+            self.insert(*output, *entry);
+        }
+    }
+    /// Turns the set of the first block of the diff into the set of the second one.
+    pub fn apply_diff(&mut self, diff: &UtxoDiff) {
+        for (output, (_, entry)) in &diff.changes {
+            match entry {
+                Some(entry) => self.insert(*output, *entry),
+                None => self.remove(output),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Transaction;
+
+    fn block(inputs: Vec<Output>, ids: &[u64]) -> Block {
+        let outputs = ids.iter().map(|&id| Output { id, amount: 1 }).collect();
+        let transaction = Transaction { inputs, outputs };
+        Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![transaction])
+    }
+
+    fn rebuilt(utxos: &UtxoSet) -> UtxoSet {
+        utxos.iter().map(|(o, e)| (*o, *e)).collect()
+    }
+
+    #[test]
+    fn root_follows_applied_and_undone_blocks() {
+        let mut utxos = UtxoSet::new();
+        utxos.apply(&block(vec![], &[5, 1, 9, 3]), 1);
+        let before = utxos.root();
+        let spent = vec![Output { id: 1, amount: 1 }, Output { id: 9, amount: 1 }];
+        let undo = utxos.apply(&block(spent, &[4, 0, 12]), 2);
+
+        let expected = rebuilt(&utxos);
+        assert_eq!(utxos.root(), expected.root());
+        assert_eq!(utxos.entries, expected.entries);
+        let ids: Vec<u64> = utxos.outputs().map(|output| output.id).collect();
+        assert_eq!(ids, [0, 3, 4, 5, 12]);
+
+        utxos.undo(&undo);
+        assert_eq!(utxos.root(), before);
+        assert_eq!(utxos.root(), rebuilt(&utxos).root());
+    }
+
+    #[test]
+    fn page_matches_a_scan_of_every_output() {
+        let entry = |height| UtxoEntry {
+            height,
+            coinbase: false,
+        };
+        let mut utxos: UtxoSet = (0..40u64)
+            .map(|n| {
+                (
+                    Output {
+                        id: n % 13,
+                        amount: n * 7 % 11,
+                    },
+                    entry(n % 5),
+                )
+            })
+            .collect();
+        utxos.insert(
+            Output {
+                id: 3,
+                amount: u64::MAX,
+            },
+            entry(1),
+        );
+        let filters = [
+            UtxoFilter::default(),
+            UtxoFilter {
+                min_id: Some(4),
+                max_id: Some(9),
+                ..UtxoFilter::default()
+            },
+            UtxoFilter {
+                min_id: Some(9),
+                max_id: Some(4),
+                ..UtxoFilter::default()
+            },
+            UtxoFilter {
+                min_amount: Some(3),
+                max_height: Some(2),
+                ..UtxoFilter::default()
+            },
+        ];
+        let cursors = [
+            None,
+            Some(Output { id: 5, amount: 4 }),
+            Some(Output { id: 99, amount: 0 }),
+        ];
+        for filter in &filters {
+            let scanned: Vec<_> = utxos.iter().filter(|(o, e)| filter.matches(o, e)).collect();
+            let total = scanned
+                .iter()
+                .fold(0u64, |total, (o, _)| total.saturating_add(o.amount));
+            for after in cursors {
+                for limit in [0, 1, 3, usize::MAX] {
+                    let page = utxos.page(filter, after, limit);
+                    let rest: Vec<_> = scanned
+                        .iter()
+                        .filter(|(o, _)| after.is_none_or(|after| **o > after))
+                        .copied()
+                        .collect();
+                    assert_eq!((page.count, page.total), (scanned.len(), total));
+                    assert_eq!(page.utxos, rest[..limit.min(rest.len())]);
+                    let more = limit > 0 && rest.len() > limit;
+                    assert_eq!(page.next, more.then(|| *page.utxos[limit - 1].0));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use super::{
+    BlockHash, ChainListener, ChainParams, Head, IndexedBlock, Network, Output, PowHasher, Sha256,
+    UtxoEntry, UtxoSet, UtxoUndo, ValidationError,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Number of blocks, including its own, after which an output counts in the confirmed
+/// [balance](struct.Wallet.html#method.balance) of a wallet.
+pub const MIN_CONFIRMATIONS: u64 = 6;
+
+/// A struct that represents a key of a [wallet](struct.Wallet.html): 32 random bytes, written
+/// like a hash, from which the id of the outputs paying to the key is derived.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WalletKey {
+    pub secret: BlockHash,
+}
+
+impl WalletKey {
+    /// Generates a new key from the random number generator of the operating system.
+    pub fn generate() -> io::Result<Self> {
+        let mut secret = [0u8; 32];
+        getrandom::getrandom(&mut secret).map_err(io::Error::from)?;
+        Ok(WalletKey {
+            secret: BlockHash(secret),
+        })
+    }
+    /// Returns the id of the outputs paying to the key: the first 8 bytes of the SHA256 hash of
+    /// the secret, as a big-endian integer.
+    pub fn id(&self) -> u64 {
+        let mut id = [0u8; 8];
+        id.copy_from_slice(&Sha256.digest(&self.secret.0)[..8]);
+        u64::from_be_bytes(id)
+    }
+}
+
+/// A struct that holds the balance of a [wallet](struct.Wallet.html#method.balance).
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Balance {
+    /// The amount of the outputs with enough confirmations that can be spent.
+    pub confirmed: u64,
+    /// The amount of the other outputs: too recent, or coinbase outputs before maturity.
+    pub unconfirmed: u64,
+}
+
+/// The outputs of a wallet that changed when it was [synced](struct.Wallet.html#method.sync).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WalletChanges {
+    /// The outputs now unspent in the main chain, with their entries, in output order.
+    pub received: Vec<(Output, UtxoEntry)>,
+    /// The outputs no longer unspent in the main chain, with their former entries, in output
+    /// order: spent, or created by blocks disconnected by a reorganization.
+    pub spent: Vec<(Output, UtxoEntry)>,
+}
+
+/// A struct that holds keys and keeps track of the unspent outputs of the main chain paying to
+/// them.
+///
+/// Outputs carry no script, so an output belongs to the wallet if its id is the
+/// [id](struct.WalletKey.html#method.id) of one of its keys, and each key should receive a
+/// single payment, see [new_key](#method.new_key). If the wallet was
+/// [loaded](#method.load) from a file, every new key is written back to it.
+///
+/// A wallet added to the [listeners](struct.Network.html#structfield.listeners) of the network
+/// follows the blocks connected to the main chain and disconnected from it as they come, once
+/// it was synced. Otherwise, it catches up with the main chain on each sync.
+///
+/// # Examples
+///
+/// ```
+/// # use mycoinlib::{Block, BlockHash, Hashable, Network, Output, Transaction, Wallet};
+/// let mut wallet = Wallet::new();
+/// let id = wallet.new_key().unwrap();
+/// let mut network = Network::new(2);
+/// let funding = Transaction {
+///     inputs: vec![],
+///     outputs: vec![Output { id, amount: 50 }],
+/// };
+/// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, vec![funding]);
+/// genesis.hash = genesis.hash();
+/// network.init(genesis.clone()).unwrap();
+///
+/// let changes = wallet.sync(&network).unwrap();
+/// assert_eq!(changes.received[0].0, Output { id, amount: 50 });
+/// assert_eq!(wallet.balance(&network.params, 2).unconfirmed, 50);
+/// network.generate(1, 100, None).unwrap();
+/// wallet.sync(&network).unwrap();
+/// assert_eq!(wallet.balance(&network.params, 2).confirmed, 50);
+///
+/// // a reorganization to a branch spending the output
+/// let payment = Transaction {
+///     inputs: vec![Output { id, amount: 50 }],
+///     outputs: vec![Output { id: 7, amount: 50 }],
+/// };
+/// let mut block = Block::new(0, BlockHash::ZERO, 0, genesis.hash, vec![payment]);
+/// block.hash = block.hash();
+/// network.submit(block.clone()).unwrap();
+/// network.generate(1, 200, Some(&block.hash)).unwrap();
+/// let changes = wallet.sync(&network).unwrap();
+/// assert_eq!(changes.spent[0].0, Output { id, amount: 50 });
+/// assert_eq!(wallet.balance(&network.params, 2).confirmed, 0);
+/// ```
+#[derive(Default)]
+pub struct Wallet {
+    path: Option<PathBuf>,
+    /// The keys of the wallet, in creation order.
+    pub keys: Vec<WalletKey>,
+    /// The [ids](struct.WalletKey.html#method.id) of the keys.
+    ids: HashSet<u64>,
+    /// The unspent outputs of the main chain paying to the keys, as of the [tip](#structfield.tip).
+    pub utxos: UtxoSet,
+    /// The main chain head the wallet was last synced with, if any.
+    pub tip: Option<Head>,
+}
+
+impl Wallet {
+    /// Creates a new wallet without keys, which is not persisted.
+    pub fn new() -> Self {
+        Wallet::default()
+    }
+    /// Loads the keys of the wallet from a JSON file, which is created with the first key if it
+    /// doesn't exist.
+    ///
+    /// The outputs of the wallet are found again on the first [sync](#method.sync).
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let keys: Vec<WalletKey> = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => return Err(e),
+        };
+        Ok(Wallet {
+            path: Some(path),
+            ids: keys.iter().map(WalletKey::id).collect(),
+            keys,
+            ..Wallet::default()
+        })
+    }
+    /// Generates a new key, saves it and returns the id to pay it to.
+    ///
+    /// A new key should be given out for every payment to receive. Unspent outputs are told
+    /// apart by their id and amount alone, so a second payment of the same amount to an id
+    /// whose first payment is still unspent creates the same output again, and the two payments
+    /// count once, in the wallet as in the chain.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, Network, Output, Transaction, Wallet};
+    /// let mut wallet = Wallet::new();
+    /// let reused = wallet.new_key().unwrap();
+    /// let fresh = wallet.new_key().unwrap();
+    /// let payment = |id| Transaction {
+    ///     inputs: vec![],
+    ///     outputs: vec![Output { id, amount: 50 }],
+    /// };
+    /// let transactions = vec![payment(reused), payment(reused), payment(fresh)];
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, transactions);
+    /// genesis.hash = genesis.hash();
+    /// let mut network = Network::new(2);
+    /// network.init(genesis).unwrap();
+    ///
+    /// wallet.sync(&network).unwrap();
+    /// assert_eq!(wallet.balance(&network.params, 1).confirmed, 100);
+    /// ```
+    pub fn new_key(&mut self) -> io::Result<u64> {
+        let key = WalletKey::generate()?;
+        self.keys.push(key);
+        self.ids.insert(key.id());
+        if let Err(e) = self.save() {
+            self.keys.pop();
+            self.ids.remove(&key.id());
+            return Err(e);
+        }
+        Ok(key.id())
+    }
+    /// Writes the keys to the wallet file, if any.
+    ///
+    /// The keys are written to a temporary file next to it, only readable by its owner on Unix,
+    /// which is synced to disk and then renamed over the wallet file, so that a crash leaves
+    /// either the previous keys or the new ones.
+    fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let mut temp = path.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        match fs::remove_file(&temp) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&temp)?;
+        file.write_all(&serde_json::to_vec(&self.keys)?)?;
+        file.sync_all()?;
+        fs::rename(&temp, path)?;
+        match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            Some(dir) => File::open(dir)?.sync_all(),
+            None => File::open(".")?.sync_all(),
+        }
+    }
+    /// Returns `true` if the output pays to a key of the wallet.
+    pub fn owns(&self, output: &Output) -> bool {
+        self.ids.contains(&output.id)
+    }
+    /// Brings the outputs of the wallet up to date with the main chain of the network, and
+    /// returns those that changed.
+    ///
+    /// The changes since the previous tip are computed with a
+    /// [diff](struct.Network.html#method.utxo_diff), which undoes the blocks a reorganization
+    /// disconnected. If the previous tip is unknown to the network, e.g. on the first sync, or if
+    /// blocks since it were [pruned](struct.Network.html#method.prune), the unspent outputs of
+    /// the main chain are scanned instead.
+    ///
+    /// Returns an error if the network was not initialized.
+    pub fn sync(&mut self, network: &Network) -> Result<WalletChanges, ValidationError> {
+        let chain_utxos = network.utxos().ok_or(ValidationError::NotInitialized)?;
+        let diff = match &self.tip {
+            Some(tip) if network.contains_block(&tip.hash) => {
+                match network.utxo_diff(&tip.hash, &network.state.hash) {
+                    Err(ValidationError::BlockPruned) => None,
+                    diff => Some(diff?),
+                }
+            }
+            _ => None,
+        };
+        let utxos: UtxoSet = match diff {
+            Some(diff) => {
+                let spent: HashSet<&Output> = diff.spent().into_iter().map(|(o, _)| o).collect();
+                self.utxos
+                    .iter()
+                    .filter(|(output, _)| !spent.contains(output))
+                    .chain(diff.created())
+                    .filter(|(output, _)| self.owns(output))
+                    .map(|(output, entry)| (*output, *entry))
+                    .collect()
+            }
+            None => chain_utxos
+                .iter()
+                .filter(|(output, _)| self.owns(output))
+                .map(|(output, entry)| (*output, *entry))
+                .collect(),
+        };
+        let changes = WalletChanges {
+            received: changed(&utxos, &self.utxos),
+            spent: changed(&self.utxos, &utxos),
+        };
+        self.utxos = utxos;
+        self.tip = Some(Head {
+            height: network.state.height,
+            totalWork: network.state.totalWork,
+            hash: network.state.hash,
+        });
+        Ok(changes)
+    }
+    /// Returns the balance of the wallet as of its [tip](#structfield.tip): an output is
+    /// confirmed once it has `min_confirmations` confirmations and, for a coinbase output, once
+    /// it is [mature](struct.ChainParams.html#structfield.coinbase_maturity).
+    ///
+    /// The amounts saturate at `u64::MAX` instead of overflowing.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use mycoinlib::{Block, BlockHash, Hashable, Network, Output, Transaction, Wallet};
+    /// let mut wallet = Wallet::new();
+    /// let id = wallet.new_key().unwrap();
+    /// let funding = |amount| Transaction {
+    ///     inputs: vec![],
+    ///     outputs: vec![Output { id, amount }],
+    /// };
+    /// let transactions = vec![funding(u64::MAX), funding(1)];
+    /// let mut genesis = Block::new(0, BlockHash::ZERO, 0, BlockHash::ZERO, transactions);
+    /// genesis.hash = genesis.hash();
+    /// let mut network = Network::new(2);
+    /// network.init(genesis).unwrap();
+    ///
+    /// wallet.sync(&network).unwrap();
+    /// assert_eq!(wallet.balance(&network.params, 1).confirmed, u64::MAX);
+    /// ```
+    pub fn balance(&self, params: &ChainParams, min_confirmations: u64) -> Balance {
+        let height = self.tip.as_ref().map_or(0, |tip| tip.height);
+        let mut balance = Balance::default();
+        for (output, entry) in self.utxos.iter() {
+            let confirmations = (height + 1).saturating_sub(entry.height);
+            let mature =
+                !entry.coinbase || entry.height == 1 || confirmations >= params.coinbase_maturity;
+            if confirmations >= min_confirmations && mature {
+                balance.confirmed = balance.confirmed.saturating_add(output.amount);
+            } else {
+                balance.unconfirmed = balance.unconfirmed.saturating_add(output.amount);
+            }
+        }
+        balance
+    }
+    /// Returns the changes to the outputs paying to the keys of the wallet.
+    fn owned(&self, undo: &UtxoUndo) -> UtxoUndo {
+        UtxoUndo {
+            spent: undo
+                .spent
+                .iter()
+                .filter(|(o, _)| self.owns(o))
+                .copied()
+                .collect(),
+            created: undo
+                .created
+                .iter()
+                .filter(|(o, _, _)| self.owns(o))
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+impl ChainListener for Wallet {
+    /// Applies the changes of the block to the outputs of the wallet, if it extends the tip. The
+    /// wallet catches up on its next [sync](#method.sync) otherwise.
+    fn connected(&mut self, block: &IndexedBlock) {
+        let extends = self
+            .tip
+            .as_ref()
+            .is_some_and(|tip| tip.hash == block.header.predecessor);
+        if !extends || block.pruned {
+            return;
+        }
+        self.utxos.redo(&self.owned(&block.undo));
+        self.tip = Some(Head {
+            height: block.height,
+            totalWork: block.total_work,
+            hash: block.header.hash,
+        });
+    }
+    /// Undoes the changes of the block to the outputs of the wallet, if it is the tip. The
+    /// wallet catches up on its next [sync](#method.sync) otherwise.
+    fn disconnected(&mut self, block: &IndexedBlock) {
+        let is_tip = self
+            .tip
+            .as_ref()
+            .is_some_and(|tip| tip.hash == block.header.hash);
+        if !is_tip || block.pruned {
+            return;
+        }
+        self.utxos.undo(&self.owned(&block.undo));
+        self.tip = Some(Head {
+            height: block.height - 1,
+            totalWork: block.total_work - u64::pow(16, block.header.difficulty),
+            hash: block.header.predecessor,
+        });
+    }
+}
+
+/// Returns the outputs of the first set, with their entries in output order, that are not in
+/// the second set with the same entry.
+fn changed(utxos: &UtxoSet, other: &UtxoSet) -> Vec<(Output, UtxoEntry)> {
+    let mut outputs: Vec<(Output, UtxoEntry)> = utxos
+        .iter()
+        .filter(|(output, entry)| other.get(output) != Some(entry))
+        .map(|(output, entry)| (*output, *entry))
+        .collect();
+    outputs.sort_unstable_by_key(|(output, _)| *output);
+    outputs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Block, Hashable, Transaction};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn listening_wallet_follows_reorganizations() {
+        let mut wallet = Wallet::new();
+        let id = wallet.new_key().unwrap();
+        let coin = Output { id, amount: 50 };
+        let block = |predecessor, inputs, outputs| {
+            let transaction = Transaction { inputs, outputs };
+            let mut block = Block::new(0, BlockHash::ZERO, 0, predecessor, vec![transaction]);
+            block.hash = block.hash();
+            block
+        };
+        let genesis = block(BlockHash::ZERO, vec![], vec![coin]);
+        let mut network = Network::new(2);
+        network.init(genesis.clone()).unwrap();
+        wallet.sync(&network).unwrap();
+        let wallet = Arc::new(Mutex::new(wallet));
+        network.listeners.push(Box::new(wallet.clone()));
+
+        let owned = |network: &Network| -> UtxoSet {
+            let utxos = network.utxos().unwrap().iter();
+            utxos
+                .filter(|(o, _)| o.id == id)
+                .map(|(o, e)| (*o, *e))
+                .collect()
+        };
+        let payment = block(genesis.hash, vec![coin], vec![Output { id: 7, amount: 50 }]);
+        network.submit(payment.clone()).unwrap();
+        assert!(wallet.lock().unwrap().utxos.is_empty());
+
+        let refund = Output { id, amount: 20 };
+        let fork = block(genesis.hash, vec![], vec![refund]);
+        network.submit(fork.clone()).unwrap();
+        network.generate(1, 100, Some(&fork.hash)).unwrap();
+        let wallet = wallet.lock().unwrap();
+        assert_eq!(wallet.utxos, owned(&network));
+        assert_eq!(wallet.utxos.len(), 2);
+        assert_eq!(wallet.tip.as_ref().unwrap().hash, network.state.hash);
+        assert_eq!(
+            wallet.tip.as_ref().unwrap().totalWork,
+            network.state.totalWork
+        );
+    }
+}